@@ -0,0 +1,327 @@
+use std::{fs, io, path::PathBuf};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{motion::Motion, settings};
+
+const HISTORY_SIZE: usize = 100; // entries kept on disk
+
+/// Text typed after `:`, `/` or `?`, with a cursor that moves by grapheme.
+#[derive(Default)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize, // byte offset, always on a grapheme boundary
+}
+
+impl LineEditor {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    // replaces the text, the cursor goes to its end
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    // terminal columns in front of the cursor
+    pub fn cursor_width(&self) -> usize {
+        self.before_cursor().width()
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    // text from byte `start` up to the cursor becomes `text`, used by completion
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        self.text.replace_range(start..self.cursor, text);
+        self.cursor = start + text.len();
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.before_cursor()
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    // start of the word in front of the cursor, whitespace before it included
+    fn word_start(&self) -> usize {
+        self.before_cursor()
+            .trim_end()
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(idx, c)| idx + c.len_utf8())
+    }
+
+    pub fn delete_backward(&mut self) {
+        let start = self.prev_boundary();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete_forward(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.cursor..end, "");
+    }
+
+    pub fn delete_word_backward(&mut self) {
+        let start = self.word_start();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    // the motions that make sense inside a single line
+    pub fn move_cursor(&mut self, motion: Motion) {
+        self.cursor = match motion {
+            Motion::Left => self.prev_boundary(),
+            Motion::Right => self.next_boundary(),
+            Motion::WordPrev | Motion::BigWordPrev => self.word_start(),
+            Motion::WordNext | Motion::BigWordNext => {
+                let rest = &self.text[self.cursor..];
+                let word = rest.len() - rest.trim_start().len();
+                let end = rest[word..]
+                    .find(char::is_whitespace)
+                    .map_or(rest.len(), |idx| word + idx);
+                self.cursor + end
+            }
+            Motion::LineStart | Motion::FirstNonBlank => 0,
+            Motion::LineEnd => self.text.len(),
+            _ => self.cursor,
+        };
+    }
+}
+
+// tab cycles through the candidates for the word in front of the cursor
+pub struct Completion {
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    start: usize, // byte offset of the completed word in the line
+}
+
+impl Completion {
+    pub fn new(start: usize, candidates: Vec<String>) -> Self {
+        Self {
+            candidates,
+            selected: 0,
+            start,
+        }
+    }
+
+    pub fn apply(&self, line: &mut LineEditor) {
+        line.replace_before_cursor(self.start, &self.candidates[self.selected]);
+    }
+
+    pub fn step(&mut self, backward: bool) {
+        let len = self.candidates.len();
+        self.selected = match backward {
+            true => (self.selected + len - 1) % len,
+            false => (self.selected + 1) % len,
+        };
+    }
+}
+
+/// Lines entered at a prompt, oldest first, browsed with up and down.
+#[derive(Default)]
+pub struct PromptHistory {
+    pub entries: Vec<String>,
+    idx: Option<usize>, // entry shown while browsing
+}
+
+impl PromptHistory {
+    pub fn push(&mut self, entry: String) {
+        self.idx = None;
+        if entry.is_empty() || self.entries.last() == Some(&entry) {
+            return;
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn reset(&mut self) {
+        self.idx = None;
+    }
+
+    // the line to show, `older` walks back in time. Past the newest entry is
+    // the empty prompt again, None leaves the prompt as it is
+    pub fn browse(&mut self, older: bool) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let idx = match (self.idx, older) {
+            (None, true) => self.entries.len() - 1,
+            (None, false) => return None,
+            (Some(idx), true) => idx.saturating_sub(1),
+            (Some(idx), false) if idx + 1 < self.entries.len() => idx + 1,
+            (Some(_), false) => {
+                self.idx = None;
+                return Some("");
+            }
+        };
+        self.idx = Some(idx);
+        Some(&self.entries[idx])
+    }
+
+    // one entry per line, newest last
+    pub fn load() -> Self {
+        let entries = history_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self { entries, idx: None }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = history_path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let skip = self.entries.len().saturating_sub(HISTORY_SIZE);
+        let mut text = self.entries[skip..].join("\n");
+        text.push('\n');
+        fs::write(path, text)
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    settings::data_dir().map(|dir| dir.join("command_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &str) -> LineEditor {
+        let mut line = LineEditor::default();
+        line.set(text.to_string());
+        line
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut line = editor("helo");
+        line.move_cursor(Motion::Left);
+        line.insert("l");
+        assert_eq!(line.as_str(), "hello");
+        assert_eq!(line.before_cursor(), "hell");
+        line.delete_forward();
+        assert_eq!(line.as_str(), "hell");
+        line.delete_backward();
+        assert_eq!(line.as_str(), "hel");
+        line.move_cursor(Motion::LineStart);
+        line.delete_backward();
+        assert_eq!(line.as_str(), "hel");
+        line.move_cursor(Motion::LineEnd);
+        line.delete_forward();
+        assert_eq!(line.as_str(), "hel");
+    }
+
+    #[test]
+    fn moves_by_grapheme() {
+        let mut line = editor("ae\u{301}b");
+        line.move_cursor(Motion::Left);
+        line.move_cursor(Motion::Left);
+        assert_eq!(line.before_cursor(), "a");
+        assert_eq!(line.cursor_width(), 1);
+        line.delete_forward();
+        assert_eq!(line.as_str(), "ab");
+        line.move_cursor(Motion::Right);
+        line.delete_backward();
+        assert_eq!(line.as_str(), "a");
+    }
+
+    #[test]
+    fn word_motions_and_deletes() {
+        let mut line = editor("s/foo bar  baz");
+        line.move_cursor(Motion::WordPrev);
+        assert_eq!(line.before_cursor(), "s/foo bar  ");
+        line.move_cursor(Motion::WordPrev);
+        assert_eq!(line.before_cursor(), "s/foo ");
+        line.move_cursor(Motion::WordNext);
+        assert_eq!(line.before_cursor(), "s/foo bar");
+        line.move_cursor(Motion::LineEnd);
+        line.delete_word_backward();
+        assert_eq!(line.as_str(), "s/foo bar  ");
+        line.delete_word_backward();
+        assert_eq!(line.as_str(), "s/foo ");
+        line.move_cursor(Motion::Left);
+        line.delete_to_start();
+        assert_eq!(line.as_str(), " ");
+        assert_eq!(line.before_cursor(), "");
+    }
+
+    #[test]
+    fn completion_replaces_the_word() {
+        let mut line = editor("e src/ma");
+        let mut completion = Completion::new(2, vec!["src/main.rs".into(), "src/macros.rs".into()]);
+        completion.apply(&mut line);
+        assert_eq!(line.as_str(), "e src/main.rs");
+        completion.step(false);
+        completion.apply(&mut line);
+        assert_eq!(line.as_str(), "e src/macros.rs");
+        completion.step(false);
+        assert_eq!(completion.selected, 0);
+        completion.step(true);
+        assert_eq!(completion.selected, 1);
+    }
+
+    #[test]
+    fn history_browse() {
+        let mut history = PromptHistory::default();
+        assert_eq!(history.browse(true), None);
+        for entry in ["w", "", "s/a/b/", "s/a/b/", "q"] {
+            history.push(entry.to_string());
+        }
+        assert_eq!(history.entries, ["w", "s/a/b/", "q"]);
+
+        assert_eq!(history.browse(false), None);
+        assert_eq!(history.browse(true), Some("q"));
+        assert_eq!(history.browse(true), Some("s/a/b/"));
+        assert_eq!(history.browse(true), Some("w"));
+        assert_eq!(history.browse(true), Some("w"));
+        assert_eq!(history.browse(false), Some("s/a/b/"));
+        assert_eq!(history.browse(false), Some("q"));
+        assert_eq!(history.browse(false), Some(""));
+        assert_eq!(history.browse(false), None);
+
+        history.browse(true);
+        history.push("e".to_string());
+        assert_eq!(history.browse(true), Some("e"));
+        history.reset();
+        assert_eq!(history.browse(true), Some("e"));
+    }
+}
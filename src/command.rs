@@ -0,0 +1,493 @@
+use std::{fs, path::Path, time::Duration};
+
+use crate::{
+    editor::Document,
+    history::UndoKind,
+    settings,
+    snippets::{
+        function::{EntityState, Snippet},
+        snippet::SnippetState,
+    },
+    substitute::{self, Scope},
+    theme::{self, Theme},
+};
+
+// what an argument is, checked before the command runs and used for completion
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    File,
+    Theme,
+    Option, // an editor setting
+    SnippetKind,
+    Entity,
+    Command,
+    Text, // anything, the command parses it
+}
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+// returns true when the editor should quit
+type Run = fn(&mut Document, &mut Snippet, &[&str]) -> bool;
+
+/// A `:` command. The first name is the one shown in help and completion, the
+/// others are short forms. Arguments are separated by whitespace.
+pub struct CommandSpec {
+    pub names: &'static [&'static str],
+    pub args: &'static [Arg],
+    pub help: &'static str,
+    run: Run,
+}
+
+const fn arg(name: &'static str, kind: ArgKind) -> Arg {
+    Arg {
+        name,
+        kind,
+        optional: false,
+    }
+}
+
+const fn optional(name: &'static str, kind: ArgKind) -> Arg {
+    Arg {
+        name,
+        kind,
+        optional: true,
+    }
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        names: &["write", "w"],
+        args: &[optional("file", ArgKind::File)],
+        help: "write the buffer, to file when given",
+        run: |doc, _, args| {
+            write(doc, args.first().map(Path::new));
+            false
+        },
+    },
+    CommandSpec {
+        names: &["wq", "x"],
+        args: &[optional("file", ArgKind::File)],
+        help: "write the buffer and quit",
+        run: |doc, _, args| write(doc, args.first().map(Path::new)),
+    },
+    CommandSpec {
+        names: &["quit", "q"],
+        args: &[],
+        help: "quit, refused while there are unsaved changes",
+        run: |doc, _, _| quit(doc),
+    },
+    CommandSpec {
+        names: &["quit!", "q!"],
+        args: &[],
+        help: "quit and discard unsaved changes",
+        run: |_, _, _| true,
+    },
+    CommandSpec {
+        names: &["open", "e", "edit"],
+        args: &[arg("file", ArgKind::File)],
+        help: "open file in place of the buffer",
+        run: |doc, snippet, args| open(doc, snippet, args[0], false),
+    },
+    CommandSpec {
+        names: &["open!", "e!", "edit!"],
+        args: &[arg("file", ArgKind::File)],
+        help: "open file and discard unsaved changes",
+        run: |doc, snippet, args| open(doc, snippet, args[0], true),
+    },
+    CommandSpec {
+        names: &["set"],
+        args: &[
+            arg("option", ArgKind::Option),
+            optional("value", ArgKind::Text),
+        ],
+        help: "show an editor setting, or change it to value",
+        run: |doc, _, args| {
+            set(doc, args[0], args.get(1).copied());
+            false
+        },
+    },
+    CommandSpec {
+        names: &["theme"],
+        args: &[optional("name", ArgKind::Theme)],
+        help: "switch the theme, shows the current one without a name",
+        run: |doc, _, args| {
+            theme(doc, args.first().copied());
+            false
+        },
+    },
+    CommandSpec {
+        names: &["earlier", "ear"],
+        args: &[optional("steps|time", ArgKind::Text)],
+        help: "undo steps, or back in time like 30s, 5m, 1h",
+        run: |doc, _, args| {
+            match parse_undo_kind(args.first().copied()) {
+                Some(kind) => doc.earlier(kind),
+                None => doc.message = Some("expected a count or a time like 30s, 5m, 1h".into()),
+            }
+            false
+        },
+    },
+    CommandSpec {
+        names: &["later", "lat"],
+        args: &[optional("steps|time", ArgKind::Text)],
+        help: "redo steps, or forward in time like 30s, 5m, 1h",
+        run: |doc, _, args| {
+            match parse_undo_kind(args.first().copied()) {
+                Some(kind) => doc.later(kind),
+                None => doc.message = Some("expected a count or a time like 30s, 5m, 1h".into()),
+            }
+            false
+        },
+    },
+    CommandSpec {
+        names: &["pundo"],
+        args: &[],
+        help: "undo the last :ps in the files besides the buffer",
+        run: |doc, _, _| {
+            substitute::undo_files(doc);
+            false
+        },
+    },
+    CommandSpec {
+        names: &["nohlsearch", "noh"],
+        args: &[],
+        help: "stop highlighting the last search",
+        run: |doc, _, _| {
+            doc.search.highlight = false;
+            false
+        },
+    },
+    CommandSpec {
+        names: &["create"],
+        args: &[arg("kind", ArgKind::SnippetKind)],
+        help: "start a new snippet at the cursor",
+        run: |doc, snippet, _| {
+            // function is the only kind so far
+            snippet.start(SnippetState::Create, doc);
+            false
+        },
+    },
+    CommandSpec {
+        names: &["delete"],
+        args: &[arg("entity", ArgKind::Entity)],
+        help: "delete a part of the function under the cursor",
+        run: |doc, snippet, args| act(doc, snippet, SnippetState::Delete, args[0]),
+    },
+    CommandSpec {
+        names: &["swap"],
+        args: &[arg("entity", ArgKind::Entity)],
+        help: "delete a part of the function under the cursor and insert its new value",
+        run: |doc, snippet, args| act(doc, snippet, SnippetState::Swap, args[0]),
+    },
+    CommandSpec {
+        names: &["help", "h"],
+        args: &[optional("command", ArgKind::Command)],
+        help: "describe a command, or list them all",
+        run: |doc, _, args| {
+            help(doc, args.first().copied());
+            false
+        },
+    },
+];
+
+pub const SNIPPET_KINDS: &[&str] = &["function"];
+
+const ENTITIES: &[(&str, EntityState)] = &[
+    ("name", EntityState::Name),
+    ("param", EntityState::Param),
+    ("result", EntityState::Result),
+    ("body", EntityState::Body),
+];
+
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.names.contains(&name))
+}
+
+impl CommandSpec {
+    pub fn name(&self) -> &'static str {
+        self.names[0]
+    }
+
+    // `set <option> [value]`
+    pub fn usage(&self) -> String {
+        let mut usage = self.name().to_string();
+        for arg in self.args {
+            match arg.optional {
+                true => usage.push_str(&format!(" [{}]", arg.name)),
+                false => usage.push_str(&format!(" <{}>", arg.name)),
+            }
+        }
+        usage
+    }
+
+    fn check(&self, args: &[&str]) -> Result<(), String> {
+        let required = self.args.iter().filter(|arg| !arg.optional).count();
+        if args.len() < required || args.len() > self.args.len() {
+            return Err(format!("usage: {}", self.usage()));
+        }
+        for (arg, value) in self.args.iter().zip(args) {
+            let known = match arg.kind {
+                ArgKind::Option => {
+                    let option = value.split_once('=').map_or(*value, |(option, _)| option);
+                    settings::OPTIONS.contains(&option)
+                }
+                ArgKind::SnippetKind => SNIPPET_KINDS.contains(value),
+                ArgKind::Entity => ENTITIES.iter().any(|(name, _)| name == value),
+                ArgKind::Command => find(value).is_some(),
+                ArgKind::File | ArgKind::Theme | ArgKind::Text => true,
+            };
+            if !known {
+                return Err(format!("unknown {}: {}", arg.name, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+// executes a `:` command line, returns true when the editor should quit
+pub fn execute(doc: &mut Document, snippet: &mut Snippet, line: &str) -> bool {
+    // the pattern of a substitution may contain spaces, it is not split up
+    if let Some((scope, args)) = substitution(line.trim_start()) {
+        substitute::start(doc, scope, args);
+        return false;
+    }
+    let mut parts = line.split_whitespace();
+    let Some(name) = parts.next() else {
+        return false;
+    };
+    let args: Vec<&str> = parts.collect();
+    let Some(spec) = find(name) else {
+        doc.message = Some(format!("unknown command: {}", name));
+        return false;
+    };
+    match spec.check(&args) {
+        Ok(()) => (spec.run)(doc, snippet, &args),
+        Err(e) => {
+            doc.message = Some(e);
+            false
+        }
+    }
+}
+
+// candidates for the word in front of the cursor, with the byte offset it starts at
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    let start = line
+        .char_indices()
+        .rfind(|(_, c)| c.is_whitespace())
+        .map_or(0, |(idx, c)| idx + c.len_utf8());
+    let word = &line[start..];
+    let mut words = line[..start].split_whitespace();
+    let Some(name) = words.next() else {
+        let names = COMMANDS.iter().map(|spec| spec.name().to_string());
+        return (start, starting_with(names, word));
+    };
+    let Some(arg) = find(name).and_then(|spec| spec.args.get(words.count())) else {
+        return (start, Vec::new());
+    };
+    let candidates = match arg.kind {
+        ArgKind::File => return complete_path(start, word),
+        ArgKind::Theme => theme::theme_names(),
+        ArgKind::Option => settings::OPTIONS.iter().map(|s| s.to_string()).collect(),
+        ArgKind::SnippetKind => SNIPPET_KINDS.iter().map(|s| s.to_string()).collect(),
+        ArgKind::Entity => ENTITIES.iter().map(|(name, _)| name.to_string()).collect(),
+        ArgKind::Command => COMMANDS
+            .iter()
+            .map(|spec| spec.name().to_string())
+            .collect(),
+        ArgKind::Text => Vec::new(),
+    };
+    (start, starting_with(candidates.into_iter(), word))
+}
+
+fn starting_with(candidates: impl Iterator<Item = String>, prefix: &str) -> Vec<String> {
+    let mut found: Vec<String> = candidates.filter(|c| c.starts_with(prefix)).collect();
+    found.sort();
+    found
+}
+
+// entries of the directory typed so far, directories end in a slash
+fn complete_path(start: usize, word: &str) -> (usize, Vec<String>) {
+    let (dir, file) = match word.rfind('/') {
+        Some(idx) => (&word[..idx + 1], &word[idx + 1..]),
+        None => ("", word),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return (start, Vec::new());
+    };
+    let names = entries.flatten().filter_map(|entry| {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // hidden files only when asked for
+        if name.starts_with('.') && !file.starts_with('.') {
+            return None;
+        }
+        let slash = if entry.path().is_dir() { "/" } else { "" };
+        Some(format!("{}{}{}", dir, name, slash))
+    });
+    (start, starting_with(names, word))
+}
+
+// s/a/b/ on the selections, %s/a/b/ on the buffer, ps/a/b/ on the whole project
+fn substitution(line: &str) -> Option<(Scope, &str)> {
+    let (scope, rest) = [
+        ("%s", Scope::Buffer),
+        ("ps", Scope::Project),
+        ("s", Scope::Selection),
+    ]
+    .into_iter()
+    .find_map(|(name, scope)| Some((scope, line.strip_prefix(name)?)))?;
+    // `:s` alone or a longer word is no substitution
+    let delimiter = rest.chars().next()?;
+    (!delimiter.is_alphanumeric() && !delimiter.is_whitespace()).then_some((scope, rest))
+}
+
+// returns true when the write succeeded
+fn write(doc: &mut Document, path: Option<&Path>) -> bool {
+    match doc.save(path) {
+        Ok(()) => true,
+        Err(e) => {
+            doc.message = Some(format!("write failed: {}", e));
+            false
+        }
+    }
+}
+
+fn open(doc: &mut Document, snippet: &mut Snippet, path: &str, force: bool) -> bool {
+    if doc.dirty && !force {
+        doc.message = Some("unsaved changes, use :w to save or :open! to discard".to_string());
+        return false;
+    }
+    match doc.load(Path::new(path)) {
+        // the entities belonged to the old text
+        Ok(()) => *snippet = Snippet::default(),
+        Err(e) => doc.message = Some(format!("open failed: {}", e)),
+    }
+    false
+}
+
+// `set tab_width 8` and `set tab_width=8` both work
+fn set(doc: &mut Document, option: &str, value: Option<&str>) {
+    let (option, value) = match option.split_once('=') {
+        Some((option, value)) => (option, Some(value)),
+        None => (option, value),
+    };
+    let Some(value) = value else {
+        let current = doc.settings.get(option).unwrap_or_default();
+        doc.message = Some(format!("{}={}", option, current));
+        return;
+    };
+    if let Err(e) = doc.settings.set(option, value) {
+        doc.message = Some(e);
+    }
+}
+
+fn act(doc: &mut Document, snippet: &mut Snippet, action: SnippetState, entity: &str) -> bool {
+    if let Some((_, entity_state)) = ENTITIES.iter().find(|(name, _)| *name == entity) {
+        snippet.act(action, *entity_state, doc);
+    }
+    false
+}
+
+fn help(doc: &mut Document, name: Option<&str>) {
+    let text = match name.and_then(find) {
+        Some(spec) => {
+            let mut text = format!("{}: {}", spec.usage(), spec.help);
+            if spec.names.len() > 1 {
+                text.push_str(&format!(" (also {})", spec.names[1..].join(", ")));
+            }
+            text
+        }
+        None => {
+            let names: Vec<&str> = COMMANDS.iter().map(CommandSpec::name).collect();
+            format!(
+                "commands: {}, s/pattern/replacement/flags",
+                names.join(", ")
+            )
+        }
+    };
+    doc.message = Some(text);
+}
+
+// without a name shows the current theme
+fn theme(doc: &mut Document, name: Option<&str>) {
+    let Some(name) = name else {
+        doc.message = Some(format!("theme: {}", doc.theme.name));
+        return;
+    };
+    match Theme::load(name, doc.settings.theme.colors) {
+        Ok(theme) => doc.theme = theme,
+        Err(e) => doc.message = Some(e.to_string()),
+    }
+}
+
+pub fn quit(doc: &mut Document) -> bool {
+    if doc.dirty {
+        doc.message = Some("unsaved changes, use :w to save or :q! to discard".to_string());
+        return false;
+    }
+    true
+}
+
+// "3" is three revisions, "30s" / "5m" / "1h" is a time period, nothing is one revision
+fn parse_undo_kind(arg: Option<&str>) -> Option<UndoKind> {
+    let Some(arg) = arg else {
+        return Some(UndoKind::Steps(1));
+    };
+    if let Ok(steps) = arg.parse::<usize>() {
+        return Some(UndoKind::Steps(steps));
+    }
+    let (unit_idx, unit) = arg.char_indices().next_back()?;
+    let value: u64 = arg[..unit_idx].parse().ok()?;
+    let seconds = match unit {
+        's' => Some(value),
+        'm' => value.checked_mul(60),
+        'h' => value.checked_mul(60 * 60),
+        'd' => value.checked_mul(60 * 60 * 24),
+        _ => None,
+    }?;
+    Some(UndoKind::TimePeriod(Duration::from_secs(seconds)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(arg: &str) -> Option<u64> {
+        match parse_undo_kind(Some(arg)) {
+            Some(UndoKind::TimePeriod(period)) => Some(period.as_secs()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn undo_kinds() {
+        assert!(matches!(parse_undo_kind(None), Some(UndoKind::Steps(1))));
+        assert!(matches!(
+            parse_undo_kind(Some("3")),
+            Some(UndoKind::Steps(3))
+        ));
+        assert_eq!(seconds("30s"), Some(30));
+        assert_eq!(seconds("5m"), Some(300));
+        assert_eq!(seconds("1h"), Some(3600));
+        assert_eq!(seconds("2d"), Some(2 * 86400));
+    }
+
+    #[test]
+    fn bad_undo_kinds() {
+        for arg in [
+            "",
+            "s",
+            "5x",
+            "5é",
+            "é",
+            "-1m",
+            "1.5h",
+            "99999999999999999d",
+        ] {
+            assert!(parse_undo_kind(Some(arg)).is_none(), "{:?}", arg);
+        }
+    }
+}
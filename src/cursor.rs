@@ -1,68 +1,283 @@
+use std::ops::Range;
+
 use ropey::Rope;
 
-// cursor location
-#[derive(Default)]
+use crate::{
+    motion::{self, Motion},
+    position::{char_col, display_col},
+    transaction::{Assoc, TranSaction},
+};
+
+// cursor location, x counts chars from the line start (see position.rs)
+#[derive(Default, Clone, Copy, PartialEq)]
 pub struct Pos {
     pub y: usize,
     pub x: usize,
 }
 
-#[derive(Default)]
-pub struct Cursor {
-    pub pos: Pos,
-    pub offset_y: usize, // first visible row in viewport (vertical scroll)
+// selected chars between anchor and head, the head is where the cursor is drawn
+// and where insert mode types. anchor == head is a plain cursor
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct CursorRange {
+    pub anchor: usize,
+    pub head: usize,
+    pub col: Option<usize>, // display column kept while moving up/down over short lines
+}
+
+impl CursorRange {
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self {
+            anchor,
+            head,
+            col: None,
+        }
+    }
+
+    pub fn point(idx: usize) -> Self {
+        Self::new(idx, idx)
+    }
+
+    pub fn from(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn to(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn len(&self) -> usize {
+        self.to() - self.from()
+    }
+
+    pub fn chars(&self) -> Range<usize> {
+        self.from()..self.to()
+    }
+
+    fn with_col(self, col: Option<usize>) -> Self {
+        Self { col, ..self }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.from() == other.from() || (other.from() < self.to() && self.from() < other.to())
+    }
 }
 
-pub enum CursorDirection {
-    Left,
-    Right,
-    Down,
-    Up,
+/// Every cursor of the document, kept sorted and without overlaps. One of them
+/// is the primary, it decides where the terminal cursor and the viewport are.
+pub struct Selection {
+    ranges: Vec<CursorRange>,
+    primary: usize,
 }
 
-impl Cursor {
-    pub fn move_cursor(&mut self, text: &Rope, direction: CursorDirection) {
-        let loc = &mut self.pos;
-        match direction {
-            CursorDirection::Right => {
-                let line_len = text.line(loc.y).len_chars();
-                if loc.x < line_len {
-                    loc.x += 1;
-                } else if loc.y + 1 < text.len_lines() {
-                    // move to start of next line
-                    loc.y += 1;
-                    loc.x = 0;
-                }
-            }
-            CursorDirection::Left => {
-                if loc.x > 0 {
-                    loc.x -= 1;
-                } else if loc.y > 0 {
-                    // move to end of previous line
-                    loc.y -= 1;
-                    let prev_len = text.line(loc.y).len_chars();
-                    loc.x = prev_len;
-                }
-            }
-            CursorDirection::Down => {
-                if loc.y + 1 < text.len_lines() {
-                    loc.y += 1;
-                    // clamp col to new line length
-                    let new_len = text.line(loc.y).len_chars();
-                    if loc.x > new_len {
-                        loc.x = new_len;
+impl Default for Selection {
+    fn default() -> Self {
+        Self::point(0)
+    }
+}
+
+impl Selection {
+    pub fn point(idx: usize) -> Self {
+        Self {
+            ranges: vec![CursorRange::point(idx)],
+            primary: 0,
+        }
+    }
+
+    pub fn ranges(&self) -> &[CursorRange] {
+        &self.ranges
+    }
+
+    pub fn primary(&self) -> CursorRange {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    // new range becomes the primary one
+    pub fn push(&mut self, range: CursorRange) {
+        self.ranges.push(range);
+        self.primary = self.ranges.len() - 1;
+        self.normalize();
+    }
+
+    // drops every cursor but the primary
+    pub fn keep_primary(&mut self) {
+        self.ranges = vec![self.primary()];
+        self.primary = 0;
+    }
+
+    // every selection shrinks to its cursor
+    pub fn collapse(&mut self) {
+        self.transform(|range| CursorRange::point(range.head));
+    }
+
+    pub fn transform(&mut self, mut f: impl FnMut(CursorRange) -> CursorRange) {
+        for range in &mut self.ranges {
+            *range = f(*range);
+        }
+        self.normalize();
+    }
+
+    // cursors follow the text they are in, typing at a cursor pushes it forward
+    pub fn map(&mut self, transaction: &TranSaction) {
+        self.transform(|range| {
+            CursorRange::new(
+                transaction.map_pos(range.anchor, Assoc::After),
+                transaction.map_pos(range.head, Assoc::After),
+            )
+        });
+    }
+
+    // sorts by position and merges cursors that ended up on top of each other
+    fn normalize(&mut self) {
+        let primary = self.primary();
+        self.ranges.sort_by_key(|range| (range.from(), range.to()));
+        let mut merged: Vec<CursorRange> = Vec::with_capacity(self.ranges.len());
+        let mut primary_idx = 0;
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.overlaps(&range) => {
+                    let (from, to) = (last.from(), last.to().max(range.to()));
+                    // keep the direction of the range that was there first
+                    *last = match last.anchor <= last.head {
+                        true => CursorRange::new(from, to),
+                        false => CursorRange::new(to, from),
+                    };
+                    if range == primary {
+                        primary_idx = merged.len() - 1;
                     }
                 }
-            }
-            CursorDirection::Up => {
-                if loc.y > 0 {
-                    loc.y -= 1;
-                    let new_len = text.line(loc.y).len_chars();
-                    if loc.x > new_len {
-                        loc.x = new_len;
+                _ => {
+                    if range == primary {
+                        primary_idx = merged.len();
                     }
+                    merged.push(range);
                 }
             }
         }
+        self.ranges = merged;
+        self.primary = primary_idx;
+    }
+}
+
+#[derive(Default)]
+pub struct Cursor {
+    pub pos: Pos,        // head of the primary range, derived from the selection
+    pub offset_y: usize, // first visible row in viewport (vertical scroll)
+    pub offset_x: usize, // first visible display column in viewport (horizontal scroll)
+    pub selection: Selection,
+}
+
+impl Cursor {
+    // moves every cursor `count` times, selections collapse onto their head
+    // unless `extend` keeps their anchor
+    pub fn move_cursor(
+        &mut self,
+        text: &Rope,
+        motion: Motion,
+        count: usize,
+        tab_width: usize,
+        extend: bool,
+    ) {
+        self.selection.transform(|range| {
+            let mut moved = CursorRange::point(range.head).with_col(range.col);
+            for _ in 0..count {
+                moved = Self::moved(text, moved, motion, tab_width);
+            }
+            match extend {
+                true => CursorRange {
+                    anchor: range.anchor,
+                    ..moved
+                },
+                false => moved,
+            }
+        });
+        self.sync_pos(text);
+    }
+
+    fn moved(text: &Rope, range: CursorRange, motion: Motion, tab_width: usize) -> CursorRange {
+        let idx = range.head;
+        if !motion.is_vertical() {
+            return CursorRange::point(motion::apply(text, idx, motion));
+        }
+        let row = text.char_to_line(idx);
+        // up and down keep the display column the cursor had before it was
+        // pushed left by a shorter line
+        let col = range
+            .col
+            .unwrap_or_else(|| display_col(text, row, idx - text.line_to_char(row), tab_width));
+        let row = match motion {
+            Motion::Down if row + 1 < text.len_lines() => row + 1,
+            Motion::Up if row > 0 => row - 1,
+            _ => row,
+        };
+        let idx = text.line_to_char(row) + char_col(text, row, col, tab_width);
+        CursorRange::point(idx).with_col(Some(col))
+    }
+
+    // keeps the primary cursor inside the viewport with `scrolloff` rows and
+    // `sidescrolloff` columns of context where possible, `column` is the display
+    // column of the cursor
+    pub fn scroll_into_view(
+        &mut self,
+        column: usize,
+        height: usize,
+        width: usize,
+        scrolloff: usize,
+        sidescrolloff: usize,
+    ) {
+        let scroll = |offset: &mut usize, pos: usize, size: usize, margin: usize| {
+            if size == 0 {
+                return;
+            }
+            let margin = margin.min((size - 1) / 2);
+            if pos < *offset + margin {
+                *offset = pos.saturating_sub(margin);
+            } else if pos + margin >= *offset + size {
+                *offset = pos + margin + 1 - size;
+            }
+        };
+        scroll(&mut self.offset_y, self.pos.y, height, scrolloff);
+        scroll(&mut self.offset_x, column, width, sidescrolloff);
+    }
+
+    // moves every cursor `rows` lines up or down and the viewport with them, like Ctrl-d/Ctrl-u
+    pub fn scroll_rows(&mut self, text: &Rope, rows: usize, motion: Motion, tab_width: usize) {
+        let last_row = text.len_lines().saturating_sub(1);
+        self.offset_y = match motion {
+            Motion::Up => self.offset_y.saturating_sub(rows),
+            _ => (self.offset_y + rows).min(last_row),
+        };
+        self.move_cursor(text, motion, rows, tab_width, false);
+    }
+
+    // puts the primary cursor line in the middle of the viewport
+    pub fn center(&mut self, height: usize) {
+        self.offset_y = self.pos.y.saturating_sub(height / 2);
+    }
+
+    // a single cursor at idx
+    pub fn set_idx(&mut self, text: &Rope, idx: usize) {
+        self.selection = Selection::point(idx.min(text.len_chars()));
+        self.sync_pos(text);
+    }
+
+    pub fn sync_pos(&mut self, text: &Rope) {
+        let idx = self.selection.primary().head.min(text.len_chars());
+        let row = text.char_to_line(idx);
+        self.pos = Pos {
+            y: row,
+            x: idx - text.line_to_char(row),
+        };
     }
 }
@@ -1,27 +1,96 @@
 use crate::{
-    cursor::{Cursor, Pos},
-    features::line_numbers::LineNumbers,
-    highlight::lex_and_style,
+    cmdline::{Completion, LineEditor, PromptHistory},
+    cursor::{Cursor, CursorRange, Pos},
+    features::{line_numbers::LineNumbers, which_key::WhichKey},
+    files,
+    highlight::HighlightCache,
+    history::{History, Step, UndoKind},
+    keymap::Keymap,
+    motion::{self, Motion},
+    pending::Pending,
+    position::{display_col, grapheme_width, line_len, next_grapheme, prev_grapheme},
+    register::{self, Registers, Yank},
+    search::{self, Search},
+    settings::Settings,
     snippets::function,
+    substitute::{FileEdit, Substitution},
+    theme::Theme,
+    transaction::{Change, TranSaction},
 };
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout, Rect},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
 };
 use ropey::Rope;
+use std::{
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 use strum::Display;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
 pub enum Mode {
     Normal,
     Insert,
+    Command,
+    Select,  // motions extend the selections instead of moving the cursors
+    Preview, // replacements of a substitution wait to be accepted
+}
+
+// what the command line is typed for
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prompt {
+    Command,
+    Search,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PasteAt {
+    Before, // P, linewise text goes above the line
+    After,  // p, linewise text goes below the line
+    Cursor, // insert mode, right where the cursor is
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Deletion {
+    CharBackward, // backspace
+    CharForward,  // delete
+    WordBackward, // C-w
+    ToLineStart,  // C-u
 }
 
 pub struct Document {
     pub editor: Editor,
     pub mode: Mode,
     pub cursor: Cursor,
+    pub path: Option<PathBuf>,
+    pub dirty: bool, // text changed since last load/save
+    pub history: History,
+    pub changes: Vec<TranSaction>, // applied since entities were last remapped
+    saved_revision: usize,
+    pub command_line: LineEditor,
+    pub command_history: PromptHistory,
+    pub completion: Option<Completion>, // tab was pressed on the command line
+    pub message: Option<String>,        // editor level feedback (save errors, quit guard...)
+    pub settings: Settings,
+    pub theme: Theme,
+    pub keymap: Keymap,
+    pub pending: Pending, // command typed so far
+    pub registers: Registers,
+    pub last_search: Option<String>,
+    pub prompt: Prompt,
+    pub search: Search,
+    pub substitution: Option<Substitution>, // shown in the preview pane until applied
+    pub file_edits: Vec<Vec<FileEdit>>,     // per `:ps`, newest last
+    pub screen: Rect,                       // terminal size, updated on resize
+    pub viewport: Rect,                     // text area on that screen, used for scrolling
+    highlights: HighlightCache,
+    pub redraw: bool,                  // something changed since the last frame
+    disk_modified: Option<SystemTime>, // modification time when loaded or saved
 }
 
 impl Document {
@@ -36,16 +105,128 @@ impl Document {
             editor: Editor { text },
             mode: Mode::Normal,
             cursor: Cursor::default(),
+            path: None,
+            dirty: false,
+            history: History::default(),
+            changes: Vec::new(),
+            saved_revision: 0,
+            command_line: LineEditor::default(),
+            command_history: PromptHistory::default(),
+            completion: None,
+            message: None,
+            settings: Settings::default(),
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            pending: Pending::default(),
+            registers: Registers::default(),
+            last_search: None,
+            prompt: Prompt::Command,
+            search: Search::default(),
+            substitution: None,
+            file_edits: Vec::new(),
+            screen: Rect::default(),
+            viewport: Rect::default(),
+            highlights: HighlightCache::default(),
+            redraw: true,
+            disk_modified: None,
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        // a missing file is opened as a new empty buffer and created on first write
+        let text = match files::read_file(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut doc = Self::new(&text);
+        doc.path = Some(path.to_path_buf());
+        doc.disk_modified = files::modified(path);
+        Ok(doc)
+    }
+
+    // replaces the buffer with another file, everything about the old text goes
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let other = Self::open(path)?;
+        self.editor = other.editor;
+        self.path = other.path;
+        self.disk_modified = other.disk_modified;
+        self.cursor = Cursor::default();
+        self.history = History::default();
+        self.saved_revision = 0;
+        self.changes.clear();
+        self.highlights = HighlightCache::default();
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn save(&mut self, path: Option<&Path>) -> io::Result<()> {
+        if let Some(path) = path {
+            self.path = Some(path.to_path_buf());
         }
+        let path = self.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no file name, use :w <path>")
+        })?;
+        files::write_file(path, &self.editor.text)?;
+        self.disk_modified = files::modified(path);
+        // later edits must not be merged into the revision that is on disk
+        self.history.seal();
+        self.saved_revision = self.history.current_revision();
+        self.dirty = false;
+        self.message = Some(format!(
+            "\"{}\" {}L written",
+            path.display(),
+            self.editor.row_get_count()
+        ));
+        Ok(())
     }
 
-    pub fn ui(&self, f: &mut Frame, snippet: &function::Snippet) {
+    // tells about changes made to the file by someone else, our own saves are known
+    pub fn check_disk(&mut self) {
+        let Some(path) = self.path.as_deref() else {
+            return;
+        };
+        let modified = files::modified(path);
+        if modified != self.disk_modified {
+            self.disk_modified = modified;
+            self.message = Some(format!("\"{}\" changed on disk", path.display()));
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        match self.path {
+            Some(ref path) => path.display().to_string(),
+            None => "[scratch]".to_string(),
+        }
+    }
+
+    // where the text ends up on a terminal of this size
+    pub fn text_area(&self, area: Rect) -> Rect {
+        let mut line_numbers = LineNumbers::new(&self.editor.text, &self.cursor, &self.theme);
+        layout_chunks(area, line_numbers.col_get_length()).1
+    }
+
+    pub fn scroll_to_cursor(&mut self) {
+        // the line number gutter grows with the line count
+        self.viewport = self.text_area(self.screen);
+        let editor = &self.settings.editor;
+        let column = self.cursor_display_col();
+        self.cursor.scroll_into_view(
+            column,
+            self.viewport.height as usize,
+            self.viewport.width as usize,
+            editor.scrolloff,
+            editor.sidescrolloff,
+        );
+    }
+
+    pub fn ui(&mut self, f: &mut Frame, snippet: &function::Snippet) {
         let content_area = f.area();
 
         // rendering line numbers and setting up editor area
-        let mut line_numbers = LineNumbers::new(&self.editor.text, &self.cursor);
+        let mut line_numbers = LineNumbers::new(&self.editor.text, &self.cursor, &self.theme);
 
-        let (line_numbs, editor_area, status_bar) =
+        let (line_numbs, editor_area, status_bar, command_bar) =
             layout_chunks(content_area, line_numbers.col_get_length());
         line_numbers.render_ui(line_numbs, f);
 
@@ -74,7 +255,8 @@ impl Document {
             .lines()
             .map(|line| ListItem::new(line.to_string()))
             .collect();
-        let list = List::new(lines.clone()).block(
+        let status_style = self.theme.get("ui.statusline");
+        let list = List::new(lines.clone()).style(status_style).block(
             Block::default()
                 .title("Snippet Entity")
                 .borders(Borders::ALL),
@@ -97,35 +279,87 @@ impl Document {
         };
         let paragraph = Paragraph::new(format!(
             r#"
+            file: {}{},
             row: {} + 1, 
             col: {} + 1, 
             idx: {},
+            cursors: {},
             mode: {},
+            pending: {},
             is_start_row: {},
             is_empty: {},
             current_char: {},
             system_message: {}
             "#,
+            self.file_name(),
+            if self.dirty { " [+]" } else { "" },
             self.cursor.pos.x,
             self.cursor.pos.y,
             self.cursor_get_idx(),
+            self.cursor.selection.len(),
             self.mode,
+            self.pending.feedback(),
             is_start_row,
             is_emphty,
             cursor_char,
             system_msg
         ))
+        .style(status_style)
         .block(Block::bordered().title("Editor State"));
         f.render_widget(paragraph, vertical[1]);
 
-        let content = self.editor.text.to_string();
-        let data = lex_and_style(&content);
+        // only the visible rows, scrolled sideways by the paragraph
+        let text = &self.editor.text;
+        let first = self.cursor.offset_y.min(text.len_lines());
+        let last = (first + editor_area.height as usize).min(text.len_lines());
+        let lines = self.highlights.lines(
+            text,
+            first..last,
+            &self.settings.highlight,
+            self.settings.editor.tab_width,
+            &self.theme,
+        );
 
-        let editor_widget = Paragraph::new(data);
+        let editor_widget = Paragraph::new(lines).scroll((0, self.cursor.offset_x as u16));
         f.render_widget(editor_widget, editor_area);
+        self.render_highlights(f, editor_area, snippet);
+        self.render_preview(f, editor_area);
+        if self.settings.editor.which_key
+            && let Some(which_key) =
+                WhichKey::new(&self.keymap, self.mode, &self.pending, &self.theme)
+        {
+            which_key.render_ui(editor_area, f);
+        }
+
+        // command line doubles as message line outside of command mode
+        let (command_text, command_style) = match self.mode {
+            Mode::Command => (
+                format!("{}{}", self.prompt_prefix(), self.command_line.as_str()),
+                self.theme.get("ui.commandline"),
+            ),
+            Mode::Preview => (
+                self.preview_help().to_string(),
+                self.theme.get("ui.message"),
+            ),
+            _ => (
+                self.message.clone().unwrap_or_default(),
+                self.theme.get("ui.message"),
+            ),
+        };
+        f.render_widget(
+            Paragraph::new(command_text).style(command_style),
+            command_bar,
+        );
+        if self.mode == Mode::Command {
+            self.render_completion(f, editor_area);
+            let x = command_bar.x + 1 + self.command_line.cursor_width() as u16;
+            f.set_cursor_position((x, command_bar.y));
+            return;
+        }
 
         let cursor_screen_row = self.cursor.pos.y.saturating_sub(self.cursor.offset_y) as u16;
-        let x = editor_area.x + self.cursor.pos.x as u16;
+        let column = self.cursor_display_col();
+        let x = editor_area.x + column.saturating_sub(self.cursor.offset_x) as u16;
         let y = editor_area.y + cursor_screen_row;
 
         if y < editor_area.y + editor_area.height {
@@ -136,36 +370,604 @@ impl Document {
     }
 }
 
+impl Document {
+    fn prompt_prefix(&self) -> &'static str {
+        match (self.prompt, self.search.backward) {
+            (Prompt::Command, _) => ":",
+            (Prompt::Search, false) => "/",
+            (Prompt::Search, true) => "?",
+        }
+    }
+
+    fn preview_help(&self) -> &'static str {
+        match self.substitution.as_ref().is_some_and(|s| s.confirm_each()) {
+            true => "replace? [y]es [n]o [a]ll [q]uit, esc cancels",
+            false => "enter replaces all, esc cancels",
+        }
+    }
+
+    // candidates of a tab completion right above the command line
+    fn render_completion(&self, f: &mut Frame, area: Rect) {
+        let Some(completion) = &self.completion else {
+            return;
+        };
+        let width = completion
+            .candidates
+            .iter()
+            .map(|candidate| candidate.width())
+            .max()
+            .unwrap_or(0) as u16;
+        let width = (width + 2).min(area.width);
+        let height = (completion.candidates.len() as u16 + 2).min(area.height / 2);
+        let pane = Rect::new(area.x, area.y + area.height - height, width, height);
+        let items: Vec<ListItem> = completion
+            .candidates
+            .iter()
+            .map(|candidate| ListItem::new(candidate.as_str()))
+            .collect();
+        let list = List::new(items)
+            .style(self.theme.get("ui.popup"))
+            .highlight_style(self.theme.get("ui.selection"))
+            .block(Block::default().borders(Borders::ALL));
+        let mut state = ListState::default();
+        state.select(Some(completion.selected));
+        f.render_widget(Clear, pane);
+        f.render_stateful_widget(list, pane, &mut state);
+    }
+
+    // the replaced lines of a substitution over the lower half of the text
+    fn render_preview(&self, f: &mut Frame, area: Rect) {
+        let Some(substitution) = &self.substitution else {
+            return;
+        };
+        let height = (area.height / 2).max(3).min(area.height);
+        let pane = Rect::new(area.x, area.y + area.height - height, area.width, height);
+        let items: Vec<ListItem> = substitution
+            .preview()
+            .into_iter()
+            .map(|entry| {
+                let mark = match entry.accepted {
+                    Some(true) => "[y] ",
+                    Some(false) => "[n] ",
+                    None => "    ",
+                };
+                ListItem::new(format!("{}{} {}", mark, entry.location, entry.line))
+            })
+            .collect();
+        let title = format!("{} replacements", substitution.len());
+        let list = List::new(items)
+            .style(self.theme.get("ui.statusline"))
+            .highlight_style(self.theme.get("ui.selection"))
+            .block(Block::default().title(title).borders(Borders::ALL));
+        let mut state = ListState::default();
+        if substitution.confirm_each() {
+            state.select(Some(substitution.current()));
+        }
+        f.render_widget(Clear, pane);
+        f.render_stateful_widget(list, pane, &mut state);
+    }
+
+    // styles drawn over the already rendered text: cursor line, selections, snippet entities
+    fn render_highlights(&self, f: &mut Frame, area: Rect, snippet: &function::Snippet) {
+        let tab_width = self.settings.editor.tab_width;
+        // cells the char at idx is drawn in, tabs and wide chars take more than one
+        let to_screen = |idx: usize| -> Option<Rect> {
+            let pos = self.editor.idx_to_pos(idx);
+            let row = pos.y.checked_sub(self.cursor.offset_y)?;
+            let display = display_col(&self.editor.text, pos.y, pos.x, tab_width);
+            let col = display.checked_sub(self.cursor.offset_x)?;
+            let c = self.editor.text.get_char(idx).unwrap_or(' ');
+            let width = grapheme_width(&c.to_string(), display, tab_width).max(1);
+            let (x, y) = (area.x as usize + col, area.y as usize + row);
+            let right = (area.x + area.width) as usize;
+            let inside = x < right && y < (area.y + area.height) as usize;
+            inside.then(|| Rect::new(x as u16, y as u16, width.min(right - x) as u16, 1))
+        };
+
+        // chars of the visible rows, ranges are cut to them before walking their chars
+        let text = &self.editor.text;
+        let first = self.cursor.offset_y.min(text.len_lines());
+        let last = (first + area.height as usize).min(text.len_lines());
+        let visible = text.line_to_char(first)..text.line_to_char(last);
+        let clip = |range: Range<usize>| range.start.max(visible.start)..range.end.min(visible.end);
+
+        let buffer = f.buffer_mut();
+        if let Some(row) = self.cursor.pos.y.checked_sub(self.cursor.offset_y)
+            && row < area.height as usize
+        {
+            let row = Rect::new(area.x, area.y + row as u16, area.width, 1);
+            buffer.set_style(row, self.theme.get("ui.cursorline"));
+        }
+
+        let selection = &self.cursor.selection;
+        let (selected, secondary) = (self.theme.get("ui.selection"), self.theme.get("ui.cursor"));
+        for (idx, range) in selection.ranges().iter().enumerate() {
+            for char_idx in clip(range.chars()) {
+                if let Some(cells) = to_screen(char_idx) {
+                    buffer.set_style(cells, selected);
+                }
+            }
+            // the primary cursor is the terminal cursor
+            if idx != selection.primary_index()
+                && let Some(cells) = to_screen(range.head)
+            {
+                buffer.set_style(cells, secondary);
+            }
+        }
+
+        if let Some(regex) = &self.search.regex
+            && self.search.highlight
+        {
+            let style = self.theme.get("ui.search");
+            for found in search::matches_in(regex, &self.editor.text, first..last) {
+                for idx in found {
+                    if let Some(cells) = to_screen(idx) {
+                        buffer.set_style(cells, style);
+                    }
+                }
+            }
+        }
+        for (range, scope) in snippet.highlight_ranges() {
+            let style = self.theme.get(scope);
+            for idx in clip(range) {
+                if let Some(cells) = to_screen(idx) {
+                    buffer.set_style(cells, style);
+                }
+            }
+        }
+    }
+}
+
 impl Document {
     // helpers around some common operations
     pub fn cursor_get_idx(&self) -> usize {
         // returs index where the curosr is located
-        self.editor.pos_to_idx(&self.cursor.pos)
+        self.cursor.selection.primary().head
+    }
+    // terminal column of the primary cursor, counted from the line start
+    pub fn cursor_display_col(&self) -> usize {
+        let pos = self.cursor.pos;
+        display_col(
+            &self.editor.text,
+            pos.y,
+            pos.x,
+            self.settings.editor.tab_width,
+        )
     }
     pub fn cursor_get_loc(&self, row_idx: usize) -> Pos {
         self.editor.idx_to_pos(row_idx)
     }
+    // leaves a single cursor at pos
     pub fn cursor_set_pos(&mut self, pos: Pos) {
-        self.cursor.pos = pos;
+        let idx = self.editor.pos_to_idx(&pos);
+        self.cursor.set_idx(&self.editor.text, idx);
+    }
+    pub fn cursor_idx_insert(&mut self, idx: usize, text: &str) {
+        let transaction = TranSaction::insert(&self.editor.text, idx, text);
+        self.apply(&transaction);
     }
-    pub fn cursor_pos_insert(&mut self, text: &str) {
-        let mut idx = self.cursor_get_idx();
-        // TODO find better solution later
-        if idx > self.editor.text.len_chars() {
-            idx = self.editor.text.len_chars()
+    pub fn remove(&mut self, range: Range<usize>) {
+        let transaction = TranSaction::delete(&self.editor.text, range);
+        self.apply(&transaction);
+    }
+    // every change of the text goes through here
+    pub fn apply(&mut self, transaction: &TranSaction) {
+        if transaction.is_empty() {
+            return;
+        }
+        let original = self.editor.text.clone();
+        if !transaction.apply(&mut self.editor.text) {
+            return;
         }
-        self.editor.text.insert(idx, text);
+        self.highlights
+            .invalidate(&original, &self.editor.text, transaction);
+        let insert_timeout = match self.mode {
+            Mode::Insert => Some(self.settings.editor.undo_group_timeout()),
+            _ => None,
+        };
+        self.history.commit(
+            transaction.clone(),
+            &original,
+            self.cursor.pos,
+            insert_timeout,
+        );
+        self.changes.push(transaction.clone());
+        self.cursor.selection.map(transaction);
+        self.cursor.sync_pos(&self.editor.text);
+        self.dirty = true;
     }
-    pub fn offset_insert(&mut self, text: &str, offset: usize) {
-        let mut idx = self.cursor_get_idx();
-        // TODO find better solution later
-        if idx > self.editor.text.len_chars() {
-            idx = self.editor.text.len_chars()
+}
+
+impl Document {
+    // edits made at every cursor at once
+    pub fn insert_at_cursors(&mut self, text: &str) {
+        let changes: Vec<Change> = self
+            .cursor
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| (range.head, range.head, Some(text.to_string())))
+            .collect();
+        let transaction = TranSaction::change(&self.editor.text, dedup(changes));
+        self.apply(&transaction);
+    }
+
+    // what `deletion` takes at every cursor, cursors with nothing to take are left out
+    pub fn deletion_ranges(&self, deletion: Deletion) -> Vec<Range<usize>> {
+        let text = &self.editor.text;
+        let range_at = |idx: usize| {
+            let line_start = motion::apply(text, idx, Motion::LineStart);
+            match deletion {
+                Deletion::CharBackward => prev_grapheme(text, idx)..idx,
+                Deletion::CharForward => idx..next_grapheme(text, idx),
+                // a cursor at a line start joins the lines
+                Deletion::WordBackward if idx == line_start => prev_grapheme(text, idx)..idx,
+                Deletion::WordBackward => {
+                    motion::apply(text, idx, Motion::WordPrev).max(line_start)..idx
+                }
+                // back to the indentation, from there or inside it back to the line start
+                Deletion::ToLineStart => {
+                    let indent = motion::apply(text, idx, Motion::FirstNonBlank);
+                    match indent < idx {
+                        true => indent..idx,
+                        false => line_start..idx,
+                    }
+                }
+            }
+        };
+        self.cursor
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| range_at(range.head))
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+
+    pub fn delete_ranges(&mut self, ranges: Vec<Range<usize>>) {
+        let changes: Vec<Change> = ranges
+            .into_iter()
+            .map(|range| (range.start, range.end, None))
+            .collect();
+        let transaction = TranSaction::change(&self.editor.text, dedup(changes));
+        self.apply(&transaction);
+    }
+
+    // spaces up to the next indentation stop, or a tab character
+    pub fn indent_at_cursors(&mut self) {
+        let settings = &self.settings.editor;
+        let text = &self.editor.text;
+        let changes: Vec<Change> = self
+            .cursor
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| {
+                let row = text.char_to_line(range.head);
+                let col = range.head - text.line_to_char(row);
+                let indent = match settings.expand_tab {
+                    true => {
+                        let col = display_col(text, row, col, settings.tab_width);
+                        let width = settings.indent_width.max(1);
+                        " ".repeat(width - col % width)
+                    }
+                    false => "\t".to_string(),
+                };
+                (range.head, range.head, Some(indent))
+            })
+            .collect();
+        let transaction = TranSaction::change(text, dedup(changes));
+        self.apply(&transaction);
+    }
+
+    // a line break that keeps the indentation of the line it splits, one level
+    // deeper where `deeper` says so
+    pub fn newline_at_cursors(&mut self, deeper: impl Fn(&Rope, usize) -> bool) {
+        let text = &self.editor.text;
+        let changes: Vec<Change> = self
+            .cursor
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| {
+                let line_start = motion::apply(text, range.head, Motion::LineStart);
+                let indent_end = motion::apply(text, range.head, Motion::FirstNonBlank);
+                let mut inserted = String::from("\n");
+                // only the part of the indentation in front of the cursor
+                inserted.extend(text.slice(line_start..indent_end.min(range.head)).chars());
+                if deeper(text, range.head) {
+                    inserted.push_str(&self.settings.editor.indent_unit());
+                }
+                (range.head, range.head, Some(inserted))
+            })
+            .collect();
+        let transaction = TranSaction::change(text, dedup(changes));
+        self.apply(&transaction);
+    }
+
+    // removes the selected text of every cursor into `register`
+    pub fn delete_selections(&mut self, register: char) {
+        let ranges: Vec<Range<usize>> = self
+            .cursor
+            .selection
+            .ranges()
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| range.chars())
+            .collect();
+        if ranges.is_empty() {
+            return;
         }
-        self.editor.text.insert(idx, text);
+        let yank = Yank {
+            values: ranges
+                .iter()
+                .map(|range| self.editor.text.slice(range.clone()).to_string())
+                .collect(),
+            linewise: false,
+            functions: Vec::new(),
+        };
+        self.store_register(register, yank, true);
+        let changes: Vec<Change> = ranges
+            .into_iter()
+            .map(|range| (range.start, range.end, None))
+            .collect();
+        let transaction = TranSaction::change(&self.editor.text, changes);
+        self.apply(&transaction);
     }
-    pub fn cursor_idx_insert(&mut self, idx: usize, text: &str) {
-        self.editor.text.insert(idx, text);
+
+    // selected text of every cursor, the whole line when nothing is selected
+    pub fn yank_selections(&mut self, register: char) {
+        let ranges = self.cursor.selection.ranges();
+        let linewise = ranges.iter().all(|range| range.is_empty());
+        let text = &self.editor.text;
+        let values = ranges
+            .iter()
+            .map(|range| match linewise {
+                true => text.line(text.char_to_line(range.head)).to_string(),
+                false => text.slice(range.chars()).to_string(),
+            })
+            .collect();
+        let count = ranges.len();
+        let yank = Yank {
+            values,
+            linewise,
+            functions: Vec::new(),
+        };
+        if self.store_register(register, yank, false) {
+            let what = if linewise { "lines" } else { "selections" };
+            self.message = Some(format!("yanked {} {} into \"{}", count, what, register));
+        }
+    }
+
+    pub fn store_register(&mut self, register: char, yank: Yank, deleted: bool) -> bool {
+        if register::is_read_only(register) {
+            self.message = Some(format!("register {} is read only", register));
+            return false;
+        }
+        if let Err(e) = self.registers.store(register, yank, deleted) {
+            self.message = Some(format!("clipboard: {}", e));
+        }
+        true
+    }
+
+    // registers with the values the document keeps itself
+    pub fn read_register(&self, register: char) -> Option<Yank> {
+        let value = match register {
+            '%' => self.path.as_ref().map(|path| path.display().to_string()),
+            '/' => self.last_search.clone(),
+            _ => return self.registers.get(register),
+        };
+        value.map(|value| Yank {
+            values: vec![value],
+            linewise: false,
+            functions: Vec::new(),
+        })
+    }
+
+    // each cursor gets its own value when there are as many values as cursors,
+    // otherwise every cursor gets all of them
+    pub fn paste(&mut self, yank: &Yank, at: PasteAt) {
+        let text = &self.editor.text;
+        let ranges = self.cursor.selection.ranges();
+        let changes: Vec<Change> = ranges
+            .iter()
+            .enumerate()
+            .map(|(idx, range)| {
+                let value = match yank.values.len() == ranges.len() {
+                    true => yank.values[idx].clone(),
+                    false => yank.joined(),
+                };
+                let row = text.char_to_line(range.head);
+                let position = match (at, yank.linewise) {
+                    (PasteAt::Cursor, _) => range.head,
+                    (PasteAt::Before, true) => text.line_to_char(row),
+                    (PasteAt::After, true) => text.line_to_char(row) + text.line(row).len_chars(),
+                    (PasteAt::Before, false) => range.from(),
+                    // after the char under the cursor, but not past the line end
+                    (PasteAt::After, false) => {
+                        let line_end = text.line_to_char(row) + line_len(text, row);
+                        match range.is_empty() {
+                            true => next_grapheme(text, range.head).min(line_end),
+                            false => range.to(),
+                        }
+                    }
+                };
+                // the last line may not end in a line break yet
+                let value = match yank.linewise
+                    && at == PasteAt::After
+                    && position == text.len_chars()
+                    && !ends_with_newline(text)
+                {
+                    true => format!("\n{}", value.trim_end_matches('\n')),
+                    false => value,
+                };
+                (position, position, Some(value))
+            })
+            .collect();
+        let transaction = TranSaction::change(&self.editor.text, dedup(changes));
+        self.apply(&transaction);
+    }
+
+    // puts open and close around every selection
+    pub fn surround_selections(&mut self, open: char, close: char) {
+        let mut changes: Vec<Change> = Vec::new();
+        for range in self.cursor.selection.ranges() {
+            // selections that touch insert close and open at the same spot
+            match changes.last_mut() {
+                Some((from, _, Some(insert))) if *from == range.from() => insert.push(open),
+                _ => changes.push((range.from(), range.from(), Some(open.to_string()))),
+            }
+            changes.push((range.to(), range.to(), Some(close.to_string())));
+        }
+        let transaction = TranSaction::change(&self.editor.text, changes);
+        self.apply(&transaction);
+    }
+
+    // selects the next occurrence of the primary selection, or of the word under
+    // the cursor when nothing is selected, searching on from the last cursor
+    pub fn add_cursor_next_match(&mut self) {
+        let primary = self.cursor.selection.primary();
+        let range = match primary.is_empty() {
+            true => self.editor.word_at(primary.head),
+            false => Some(primary.chars()),
+        };
+        let Some(range) = range.filter(|range| !range.is_empty()) else {
+            self.message = Some("no word under the cursor".to_string());
+            return;
+        };
+        if primary.is_empty() {
+            // the word itself becomes the first selection
+            self.cursor
+                .selection
+                .transform(|current| match current == primary {
+                    true => CursorRange::new(range.start, range.end),
+                    false => current,
+                });
+        }
+
+        let needle = self.editor.text.slice(range.clone()).to_string();
+        let text = self.editor.text.to_string();
+        let last_end = self.cursor.selection.ranges().last().map_or(0, |r| r.to());
+        let start_byte = self.editor.text.char_to_byte(last_end);
+        let selected = |from: usize| {
+            self.cursor
+                .selection
+                .ranges()
+                .iter()
+                .any(|r| r.from() == from && r.len() == range.len())
+        };
+        // first match after the last cursor that is not selected yet, wrapping around
+        let (after, before): (Vec<usize>, Vec<usize>) = text
+            .match_indices(&needle)
+            .map(|(byte, _)| byte)
+            .partition(|byte| *byte >= start_byte);
+        let found = after
+            .into_iter()
+            .chain(before)
+            .map(|byte| self.editor.text.byte_to_char(byte))
+            .find(|from| !selected(*from));
+        match found {
+            Some(from) => self
+                .cursor
+                .selection
+                .push(CursorRange::new(from, from + range.len())),
+            None => self.message = Some("no more matches".to_string()),
+        }
+        self.cursor.sync_pos(&self.editor.text);
+    }
+
+    // a cursor on every whole word occurrence of the word under the cursor within `scope`
+    pub fn select_word_occurrences(&mut self, scope: Range<usize>) -> usize {
+        let head = self.cursor_get_idx();
+        let Some(word) = self.editor.word_at(head).filter(|word| !word.is_empty()) else {
+            return 0;
+        };
+        let needle: Vec<char> = self.editor.text.slice(word.clone()).chars().collect();
+        let end = scope.end.min(self.editor.text.len_chars());
+        let mut ranges = Vec::new();
+        let mut idx = scope.start;
+        while idx + needle.len() <= end {
+            let is_match = needle
+                .iter()
+                .enumerate()
+                .all(|(offset, c)| self.editor.text.char(idx + offset) == *c);
+            if is_match && self.editor.word_at(idx) == Some(idx..idx + needle.len()) {
+                ranges.push(idx..idx + needle.len());
+                idx += needle.len();
+            } else {
+                idx += 1;
+            }
+        }
+        let count = ranges.len();
+        for range in ranges.into_iter().filter(|range| *range != word) {
+            self.cursor
+                .selection
+                .push(CursorRange::new(range.start, range.end));
+        }
+        // the occurrence under the cursor stays primary
+        self.cursor
+            .selection
+            .push(CursorRange::new(word.start, word.end));
+        self.cursor.sync_pos(&self.editor.text);
+        count
+    }
+}
+
+fn ends_with_newline(text: &Rope) -> bool {
+    text.len_chars() > 0 && text.char(text.len_chars() - 1) == '\n'
+}
+
+// cursors on the same spot would make overlapping changes
+fn dedup(mut changes: Vec<Change>) -> Vec<Change> {
+    changes.dedup_by(|next, previous| next.0 < previous.1 || next.0 == previous.0);
+    changes
+}
+
+impl Document {
+    // undo tree navigation
+    pub fn undo(&mut self) {
+        match self.history.undo_step() {
+            Some(step) => self.history_jump(vec![step]),
+            None => self.message = Some("already at oldest change".to_string()),
+        }
+    }
+    pub fn redo(&mut self) {
+        match self.history.redo_step() {
+            Some(step) => self.history_jump(vec![step]),
+            None => self.message = Some("already at newest change".to_string()),
+        }
+    }
+    pub fn earlier(&mut self, kind: UndoKind) {
+        let steps = self.history.earlier(kind);
+        self.history_jump(steps);
+    }
+    pub fn later(&mut self, kind: UndoKind) {
+        let steps = self.history.later(kind);
+        self.history_jump(steps);
+    }
+    fn history_jump(&mut self, steps: Vec<Step>) {
+        // moving through history closes whatever edit group is open
+        self.history.end_group();
+        for step in steps {
+            let (transaction, pos) = self.history.step(step, self.cursor.pos);
+            let original = self.editor.text.clone();
+            if !transaction.apply(&mut self.editor.text) {
+                // the text is not what the revision was made for, stay where the text is
+                let back = match step {
+                    Step::Undo(revision) => Step::Redo(revision),
+                    Step::Redo(revision) => Step::Undo(revision),
+                };
+                self.history.step(back, self.cursor.pos);
+                self.message = Some("history does not match the text, jump stopped".to_string());
+                break;
+            }
+            self.highlights
+                .invalidate(&original, &self.editor.text, transaction);
+            self.changes.push(transaction.clone());
+            let idx = self.editor.pos_to_idx(&pos);
+            self.cursor.set_idx(&self.editor.text, idx);
+        }
+        self.dirty = self.history.current_revision() != self.saved_revision;
     }
     pub fn cursor_get_char(&self) -> Option<char> {
         let idx = self.cursor_get_idx();
@@ -180,6 +982,7 @@ impl Editor {
     pub fn row_get_count(&self) -> usize {
         self.text.len_lines() // lines is considered to be a line break
     }
+    #[allow(dead_code)]
     pub fn row_get_length(&self, row_idx: usize) -> usize {
         self.text.line(row_idx).len_chars()
     }
@@ -202,18 +1005,39 @@ impl Editor {
 }
 
 impl Editor {
-    pub fn is_start_row(&self, idx: usize) -> bool {
-        idx == 0 || self.text.char(idx - 1) == '\n'
+    // identifier chars around idx, also matches when idx is right after the word
+    pub fn word_at(&self, idx: usize) -> Option<Range<usize>> {
+        let is_word = |idx: usize| {
+            self.text
+                .get_char(idx)
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        };
+        let idx = match is_word(idx) {
+            true => idx,
+            false if idx > 0 && is_word(idx - 1) => idx - 1,
+            false => return None,
+        };
+        let start = (0..idx)
+            .rev()
+            .take_while(|i| is_word(*i))
+            .last()
+            .unwrap_or(idx);
+        let end = (idx..self.text.len_chars())
+            .take_while(|i| is_word(*i))
+            .last()
+            .map_or(idx, |i| i + 1);
+        Some(start..end)
     }
 }
 
-fn layout_chunks(area: Rect, line_numbers: u16) -> (Rect, Rect, Rect) {
-    // Split vertically: main area and status bar
+fn layout_chunks(area: Rect, line_numbers: u16) -> (Rect, Rect, Rect, Rect) {
+    // Split vertically: main area, status bar and command line
     let vertical = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Min(1),         // Text + line numbers
             Constraint::Percentage(70), // Status bar
+            Constraint::Length(1),      // Command line
         ])
         .split(area);
 
@@ -229,6 +1053,7 @@ fn layout_chunks(area: Rect, line_numbers: u16) -> (Rect, Rect, Rect) {
     let line_numbers = horizontal[0];
     let text_area = horizontal[1];
     let status_bar = vertical[1];
+    let command_bar = vertical[2];
 
-    (line_numbers, text_area, status_bar)
+    (line_numbers, text_area, status_bar, command_bar)
 }
@@ -0,0 +1,108 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossterm::event::{self, Event};
+
+use crate::files;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// everything the main loop wakes up for
+pub enum AppEvent {
+    Terminal(Event),
+    FileChanged(PathBuf), // modification time on disk moved
+    KeyTimeout,           // a multi key binding was not continued in time
+}
+
+/// Single queue every source of events feeds into, the main loop blocks on it
+/// instead of polling. Background workers (terminal reader, file watcher, later
+/// LSP clients and timers) get a clone of the sender.
+pub struct Events {
+    sender: Sender<AppEvent>,
+    receiver: Receiver<AppEvent>,
+    watched: Arc<Mutex<Option<Watched>>>, // file the watcher polls, None while unnamed
+}
+
+struct Watched {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            watched: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn spawn_terminal(&self) {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            while let Ok(event) = event::read() {
+                if sender.send(AppEvent::Terminal(event)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // polls the modification time of the watched file, cheap enough for a single file
+    pub fn spawn_watcher(&self) {
+        let sender = self.sender.clone();
+        let watched = Arc::clone(&self.watched);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let changed = match watched.lock().ok().as_deref_mut() {
+                    Some(Some(watched)) => {
+                        let modified = files::modified(&watched.path);
+                        let changed = modified != watched.modified;
+                        watched.modified = modified;
+                        changed.then(|| watched.path.clone())
+                    }
+                    _ => None,
+                };
+                if let Some(path) = changed
+                    && sender.send(AppEvent::FileChanged(path)).is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    // the buffer was opened or saved under another name
+    pub fn watch(&self, path: Option<PathBuf>) {
+        if let Ok(mut watched) = self.watched.lock() {
+            *watched = path.map(|path| Watched {
+                modified: files::modified(&path),
+                path,
+            });
+        }
+    }
+
+    // blocks until something happens, or until `deadline` passes
+    pub fn next(&self, deadline: Option<Instant>) -> io::Result<AppEvent> {
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "event sources closed");
+        let Some(deadline) = deadline else {
+            return self.receiver.recv().map_err(|_| closed());
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(event),
+            Err(RecvTimeoutError::Timeout) => Ok(AppEvent::KeyTimeout),
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        }
+    }
+}
@@ -1,24 +1,21 @@
-use crate::cursor::Cursor;
-use ratatui::{
-    Frame,
-    layout::Rect,
-    style::{Color, Modifier, Style},
-    text::Line,
-    widgets::Paragraph,
-};
+use crate::{cursor::Cursor, theme::Theme};
+use ratatui::{Frame, layout::Rect, style::Style, text::Line, widgets::Paragraph};
 use ropey::Rope;
 
 pub struct LineNumbers<'a> {
     text: &'a Rope,
     cursor: &'a Cursor,
     style: Style,
+    current_style: Style,
     col_length: u16,
 }
 
 impl<'a> LineNumbers<'a> {
-    pub fn new(text: &'a Rope, cursor: &'a Cursor) -> Self {
+    pub fn new(text: &'a Rope, cursor: &'a Cursor, theme: &Theme) -> Self {
+        let style = theme.get("ui.linenr");
         Self {
-            style: Style::default().fg(Color::Red),
+            style,
+            current_style: style.patch(theme.get("ui.linenr.selected")),
             col_length: 0,
             text,
             cursor,
@@ -36,8 +33,8 @@ impl<'a> LineNumbers<'a> {
 
     pub fn render_ui(&self, render_space: Rect, f: &mut Frame) {
         // Determine visible range based on offset_row and editor height
-        let start_row = self.cursor.offset_y;
-        let end_row = self.text.len_lines();
+        let start_row = self.cursor.offset_y.min(self.text.len_lines());
+        let end_row = (start_row + render_space.height as usize).min(self.text.len_lines());
 
         let line_numbers: Vec<Line> = (start_row..end_row)
             .map(|i| {
@@ -49,7 +46,7 @@ impl<'a> LineNumbers<'a> {
 
                 // highlight number where the cursor have landed
                 if i == self.cursor.pos.y {
-                    line = line.style(self.style.fg(Color::LightCyan).add_modifier(Modifier::BOLD));
+                    line = line.style(self.current_style);
                 }
                 line
             })
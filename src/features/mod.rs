@@ -1 +1,2 @@
 pub mod line_numbers;
+pub mod which_key;
@@ -0,0 +1,114 @@
+use crate::{
+    editor::Mode,
+    keymap::{Command, KeyTrie, Keymap, sequence_to_string},
+    pending::{Pending, PendingChar},
+    theme::Theme,
+};
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Style,
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+use unicode_width::UnicodeWidthStr;
+
+/// Popup with the keys that can follow what is pending: the entity keys after an
+/// action or a text object, the rest of a multi key binding otherwise. It reads
+/// the same keymap the dispatcher uses, so new bindings and entities show up in
+/// it without changes here.
+pub struct WhichKey {
+    title: String,
+    entries: Vec<(String, String)>, // key, description
+    style: Style,
+    key_style: Style,
+}
+
+impl WhichKey {
+    pub fn new(keymap: &Keymap, mode: Mode, pending: &Pending, theme: &Theme) -> Option<Self> {
+        let (title, mut entries) = match pending.awaiting {
+            Some(PendingChar::Action(_)) => {
+                // actions only apply to the parts of an entity
+                let entries = follow_ups(&keymap.entity, |command| {
+                    command.to_entity_state().is_some()
+                });
+                (pending.feedback(), entries)
+            }
+            Some(PendingChar::Object { .. }) => {
+                let mut entries = follow_ups(&keymap.entity, |_| true);
+                entries.push(("( [ { < ' \"".to_string(), "delimiter pair".to_string()));
+                (pending.feedback(), entries)
+            }
+            Some(_) => return None,
+            // text is typed there, the start of a binding may just be a letter
+            None if matches!(mode, Mode::Insert | Mode::Command) => return None,
+            None if pending.sequence.is_empty() => return None,
+            None => {
+                let node = keymap.bindings(mode).get(&pending.sequence)?;
+                (
+                    sequence_to_string(&pending.sequence),
+                    follow_ups(node, |_| true),
+                )
+            }
+        };
+        if entries.is_empty() {
+            return None;
+        }
+        entries.sort();
+        Some(Self {
+            title,
+            entries,
+            style: theme.get("ui.popup"),
+            key_style: theme.get("ui.popup.key"),
+        })
+    }
+
+    // in the bottom right corner of the text
+    pub fn render_ui(&self, area: Rect, f: &mut Frame) {
+        let key_width = self.entries.iter().map(|(key, _)| key.width()).max();
+        let key_width = key_width.unwrap_or(0);
+        let lines: Vec<Line> = self
+            .entries
+            .iter()
+            .map(|(key, description)| {
+                Line::from(vec![
+                    Span::styled(format!("{:<key_width$}", key), self.key_style),
+                    Span::raw("  "),
+                    Span::raw(description.clone()),
+                ])
+            })
+            .collect();
+        let content_width = lines.iter().map(Line::width).max().unwrap_or(0);
+        let width = (content_width.max(self.title.width()) as u16 + 4).min(area.width);
+        let height = (lines.len() as u16 + 2).min(area.height);
+        let popup = Rect::new(
+            area.x + area.width - width,
+            area.y + area.height - height,
+            width,
+            height,
+        );
+        let paragraph = Paragraph::new(lines).style(self.style).block(
+            Block::default()
+                .title(self.title.as_str())
+                .borders(Borders::ALL),
+        );
+        f.render_widget(Clear, popup);
+        f.render_widget(paragraph, popup);
+    }
+}
+
+// the keys right below `node`, a key that leads to more keys is shown as a group
+fn follow_ups(node: &KeyTrie, keep: impl Fn(Command) -> bool) -> Vec<(String, String)> {
+    node.children
+        .iter()
+        .filter_map(|(key, child)| {
+            let description = match (child.command, child.children.is_empty()) {
+                (Some(Command::NoOp), true) | (None, true) => return None,
+                (Some(command), true) if !keep(command) => return None,
+                (Some(command), true) => command.description(),
+                (_, false) => "+more".to_string(),
+            };
+            Some((key.to_string(), description))
+        })
+        .collect()
+}
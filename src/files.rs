@@ -1,11 +1,50 @@
-use std::fs;
-
-pub fn read_file(path: &str) -> Result<String, ()> {
-    match fs::read_to_string(path) {
-        Ok(contents) => Ok(contents),
-        Err(e) => {
-            println!("Failed to read file: {}", e);
-            Err(())
-        }
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use ropey::Rope;
+
+pub fn read_file(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+}
+
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// writes into a sibling temp file and renames it over the target, so a failed
+// write never leaves a half written file behind
+pub fn write_file(path: &Path, text: &Rope) -> io::Result<()> {
+    let tmp_path = temp_path(path)?;
+    let result = write_temp(path, &tmp_path, text).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
     }
+    result
+}
+
+fn write_temp(path: &Path, tmp_path: &Path, text: &Rope) -> io::Result<()> {
+    let file = fs::File::create(tmp_path)?;
+    // keep permissions of the file we are replacing
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    let mut writer = BufWriter::new(file);
+    text.write_to(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let tmp_name = format!(".{}.elarge-tmp", file_name.to_string_lossy());
+    Ok(path.with_file_name(tmp_name))
 }
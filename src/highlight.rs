@@ -1,9 +1,15 @@
 use logos::Logos;
 
+use crate::{
+    position::expand_tabs, settings::HighlightSettings, theme::Theme, transaction::TranSaction,
+};
+
 use ratatui::{
-    style::{Color, Style, Stylize},
-    text::{Line, Span, Text},
+    style::Style,
+    text::{Line, Span},
 };
+use ropey::Rope;
+use std::ops::Range;
 
 #[derive(Logos, Debug, PartialEq)]
 pub enum Token {
@@ -62,68 +68,228 @@ pub enum Token {
     Newline,
 }
 
-fn token_to_style(token: &Token) -> Style {
+// theme scope of each token, see themes/default.toml
+fn token_to_scope(token: &Token) -> &'static str {
     match token {
-        Token::Let | Token::Return => Style::default().fg(Color::LightBlue).bold(),
-        Token::Fn | Token::TypeI32 | Token::TypeU64 | Token::TypeBool | Token::TypeString => {
-            Style::default()
-                .fg(Color::Cyan)
-                .underline_color(Color::Yellow)
+        Token::Let | Token::Return => "keyword",
+        Token::Fn => "keyword.function",
+        Token::TypeI32 | Token::TypeU64 | Token::TypeBool | Token::TypeString => "type",
+        Token::IntegerLiteral => "constant.number",
+        Token::StringLiteral => "string",
+        Token::Ident => "variable",
+        Token::Semicolon | Token::Comma | Token::Colon => "punctuation.delimiter",
+        Token::Assign | Token::Plus | Token::Arrow => "operator",
+        Token::Whitespace | Token::Newline => "whitespace",
+        Token::CloseParen | Token::OpenParen => "punctuation.bracket",
+        Token::OpenBrace | Token::CloseBrace => "punctuation.brace",
+    }
+}
+
+// byte range inside the line and the theme scope it is drawn with
+type LineTokens = Vec<(Range<usize>, Option<&'static str>)>;
+
+struct LexedLine {
+    tokens: LineTokens,
+    in_string: bool,      // starts inside a string opened on a line above
+    ends_in_string: bool, // a string is still open at its end
+}
+
+// lines are lexed on their own, a string that spans lines is carried over
+// by `in_string`
+fn lex_line(line: &str, in_string: bool) -> (LineTokens, bool) {
+    let mut tokens = LineTokens::new();
+    let mut start = 0;
+    if in_string {
+        match line.find('"') {
+            Some(end) => {
+                tokens.push((0..end + 1, Some("string")));
+                start = end + 1;
+            }
+            None => return (vec![(0..line.len(), Some("string"))], true),
         }
-        Token::IntegerLiteral => Style::default().fg(Color::LightYellow),
-        Token::StringLiteral => Style::default().fg(Color::Yellow),
-        Token::Ident => Style::default().fg(Color::Red).underline_color(Color::Red),
-        Token::Semicolon | Token::Comma | Token::Colon => Style::default().fg(Color::DarkGray),
-        Token::Assign | Token::Plus | Token::Arrow => Style::default().fg(Color::Magenta),
-        Token::Whitespace | Token::Newline => Style::default().fg(Color::Green),
-        Token::CloseParen | Token::OpenParen => Style::default().fg(Color::Green),
-        Token::OpenBrace | Token::CloseBrace => Style::default().fg(Color::LightBlue),
     }
+    let rest = &line[start..];
+    for (token, range) in Token::lexer(rest).spanned() {
+        let range = range.start + start..range.end + start;
+        // a quote without its closing one opens a string that goes on below
+        if token.is_err() && line[range.start..].starts_with('"') {
+            tokens.push((range.start..line.len(), Some("string")));
+            return (tokens, true);
+        }
+        tokens.push((range, token.ok().as_ref().map(token_to_scope)));
+    }
+    (tokens, false)
+}
+
+fn line_text(text: &Rope, row: usize) -> String {
+    let line = text.line(row).to_string();
+    line.trim_end_matches(['\n', '\r']).to_string()
 }
 
-pub fn lex_and_style(text: &str) -> Text<'_> {
-    let lexer = Token::lexer(text);
-    let mut lines: Vec<Line> = Vec::new();
-    let mut current_spans: Vec<Span> = Vec::new();
+// lines lexed above the view when none of them is known yet, a string that
+// opened further up than that is not seen
+const SYNC_LINES: usize = 100;
 
-    for (token, span_range) in lexer.spanned() {
-        let slice = &text[span_range.clone()];
+/// Tokens of every line that was drawn, so a frame only lexes lines that were
+/// edited or scrolled into view, starting from the nearest known line above
+/// them. Scopes are stored instead of styles so switching themes does not
+/// throw the cache away.
+#[derive(Default)]
+pub struct HighlightCache {
+    lines: Vec<Option<LexedLine>>,
+    checked: usize, // lines above it agree with the string state of the lines above them
+}
 
-        let style = if let Ok(x) = token {
-            token_to_style(&x)
-        } else {
-            Style::new()
+impl HighlightCache {
+    // lines touched by the transaction are lexed again, the ones below it
+    // move up or down with the line count
+    pub fn invalidate(&mut self, original: &Rope, text: &Rope, transaction: &TranSaction) {
+        let Some((from, old_end, new_end)) = transaction.changed_range() else {
+            return;
         };
+        let first = original.char_to_line(from);
+        if first >= self.lines.len() {
+            return;
+        }
+        let old_last = original.char_to_line(old_end).min(self.lines.len() - 1);
+        let new_last = text.char_to_line(new_end);
+        self.lines
+            .splice(first..=old_last, (first..=new_last).map(|_| None));
+        self.checked = self.checked.min(first);
+    }
 
-        let mut start = 0;
-        for (idx, ch) in slice.char_indices() {
-            if ch == '\n' {
-                // piece includes up to and including this newline
-                let piece = &slice[start..=idx];
-                // remove the trailing newline for visual span content
-                let content = &piece[..piece.len() - 1];
-                if !content.is_empty() {
-                    current_spans.push(Span::styled(content.to_string(), style));
-                }
-                // finalize current line and start a new one
-                lines.push(Line::from(current_spans));
-                current_spans = Vec::new();
-                start = idx + ch.len_utf8();
+    // lexes what is missing in `rows`, and the lines whose string state changed
+    // because a line above them did. Starts below the nearest checked line
+    fn update(&mut self, text: &Rope, rows: Range<usize>) {
+        let window = rows.start.saturating_sub(SYNC_LINES);
+        let checkpoint = (window..rows.start.min(self.checked))
+            .rev()
+            .find_map(|row| Some((row, self.lines[row].as_ref()?.ends_in_string)));
+        let (begin, mut in_string) = match checkpoint {
+            Some((row, ends_in_string)) => (row + 1, ends_in_string),
+            None => (window, false),
+        };
+        let mut changed = false; // string state at the end of the last line
+        for row in begin..rows.end {
+            let lexed = self.lines[row].as_ref();
+            changed = false;
+            if lexed.is_none_or(|lexed| lexed.in_string != in_string) {
+                let ended_in_string = lexed.map(|lexed| lexed.ends_in_string);
+                let (tokens, ends_in_string) = lex_line(&line_text(text, row), in_string);
+                changed = ended_in_string != Some(ends_in_string);
+                self.lines[row] = Some(LexedLine {
+                    tokens,
+                    in_string,
+                    ends_in_string,
+                });
             }
+            in_string = self.lines[row]
+                .as_ref()
+                .is_some_and(|lexed| lexed.ends_in_string);
         }
+        // lines further down only stay checked when the state they start from did not change
+        self.checked = match changed {
+            true => rows.end,
+            false => self.checked.max(rows.end),
+        };
+    }
 
-        if start < slice.len() {
-            let trailing = &slice[start..];
-            if !trailing.is_empty() {
-                current_spans.push(Span::styled(trailing.to_string(), style));
-            }
-        } else {
+    pub fn lines(
+        &mut self,
+        text: &Rope,
+        rows: Range<usize>,
+        settings: &HighlightSettings,
+        tab_width: usize,
+        theme: &Theme,
+    ) -> Vec<Line<'static>> {
+        self.lines.resize_with(text.len_lines(), || None);
+        if settings.enabled {
+            self.update(text, rows.clone());
         }
+        rows.map(|row| {
+            let line = line_text(text, row);
+            let Some(lexed) = self.lines[row].as_ref().filter(|_| settings.enabled) else {
+                return Line::raw(expand_tabs(&line, 0, tab_width).0);
+            };
+            // tabs are expanded per token, the column carries over between them
+            let mut col = 0;
+            let spans: Vec<Span> = lexed
+                .tokens
+                .iter()
+                .map(|(range, scope)| {
+                    let style = scope.map_or(Style::new(), |scope| theme.get(scope));
+                    let (content, end) = expand_tabs(&line[range.clone()], col, tab_width);
+                    col = end;
+                    Span::styled(content, style)
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(cache: &HighlightCache, row: usize) -> Vec<Option<&'static str>> {
+        let lexed = cache.lines[row].as_ref().unwrap();
+        lexed.tokens.iter().map(|(_, scope)| *scope).collect()
+    }
+
+    fn lexed(cache: &HighlightCache) -> usize {
+        cache.lines.iter().flatten().count()
     }
-    if !current_spans.is_empty() {
-        lines.push(Line::from(current_spans));
-    } else if lines.is_empty() {
-        lines.push(Line::from(Vec::<Span>::new()));
+
+    fn cache(text: &Rope) -> HighlightCache {
+        let mut cache = HighlightCache::default();
+        cache.lines.resize_with(text.len_lines(), || None);
+        cache
+    }
+
+    #[test]
+    fn strings_go_on_across_lines() {
+        let text = Rope::from("let a = \"one\ntwo\nthree\" + 1\n");
+        let mut cache = cache(&text);
+        cache.update(&text, 0..3);
+        assert_eq!(scopes(&cache, 0).last(), Some(&Some("string")));
+        assert_eq!(scopes(&cache, 1), [Some("string")]);
+        assert_eq!(
+            scopes(&cache, 2),
+            [
+                Some("string"),
+                Some("whitespace"),
+                Some("operator"),
+                Some("whitespace"),
+                Some("constant.number")
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_above_relex_what_follows() {
+        let original = Rope::from("a\nb\nc\n");
+        let mut cache = cache(&original);
+        cache.update(&original, 0..4);
+        assert_eq!(scopes(&cache, 2), [Some("variable")]);
+
+        let transaction = TranSaction::insert(&original, 0, "\"");
+        let mut text = original.clone();
+        assert!(transaction.apply(&mut text));
+        cache.invalidate(&original, &text, &transaction);
+        cache.update(&text, 2..3);
+        assert_eq!(scopes(&cache, 2), [Some("string")]);
+    }
+
+    #[test]
+    fn only_lines_near_the_view_are_lexed() {
+        let text = Rope::from("x\n".repeat(10_000));
+        let mut cache = cache(&text);
+        cache.update(&text, 9_950..10_000);
+        assert_eq!(lexed(&cache), 50 + SYNC_LINES);
+        // scrolling down goes on from the lines known above
+        cache.update(&text, 9_960..10_001);
+        assert_eq!(lexed(&cache), 51 + SYNC_LINES);
     }
-    Text::from(lines)
 }
@@ -0,0 +1,442 @@
+use std::time::{Duration, Instant};
+
+use ropey::Rope;
+
+use crate::{cursor::Pos, transaction::TranSaction};
+
+struct Revision {
+    parent: usize,
+    last_child: Option<usize>, // redo follows the most recently created branch
+    transaction: TranSaction,  // parent -> this revision
+    inversion: TranSaction,    // this revision -> parent
+    cursor_before: Pos,        // restored when this revision is undone
+    cursor_after: Pos,         // restored when this revision is redone
+    timestamp: Instant,
+    mergeable: bool, // following insert mode edits may be folded into this revision
+}
+
+// one move along the undo tree, revision is the one being left (undo) or entered (redo)
+#[derive(Clone, Copy)]
+pub enum Step {
+    Undo(usize),
+    Redo(usize),
+}
+
+pub enum UndoKind {
+    Steps(usize),
+    TimePeriod(Duration),
+}
+
+/// Undo tree, every commit adds a child to the current revision so undoing and
+/// then editing starts a new branch instead of dropping the old one. Revisions
+/// are stored in creation order which is what `earlier`/`later` walk through.
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+    grouping: bool, // every commit goes into one revision until end_group
+    group_revision: Option<usize>, // revision created by the open group
+}
+
+impl Default for History {
+    fn default() -> Self {
+        // root revision, the text as it was loaded
+        Self {
+            revisions: vec![Revision {
+                parent: 0,
+                last_child: None,
+                transaction: TranSaction::default(),
+                inversion: TranSaction::default(),
+                cursor_before: Pos::default(),
+                cursor_after: Pos::default(),
+                timestamp: Instant::now(),
+                mergeable: false,
+            }],
+            current: 0,
+            grouping: false,
+            group_revision: None,
+        }
+    }
+}
+
+impl History {
+    pub fn current_revision(&self) -> usize {
+        self.current
+    }
+
+    pub fn revision_count(&self) -> usize {
+        self.revisions.len()
+    }
+
+    // `original` is the text before the transaction was applied. insert mode edits
+    // pass a timeout, edits closer together than that end up in one revision
+    pub fn commit(
+        &mut self,
+        transaction: TranSaction,
+        original: &Rope,
+        cursor_before: Pos,
+        insert_timeout: Option<Duration>,
+    ) {
+        let inversion = transaction.invert(original);
+        let now = Instant::now();
+
+        let current = &mut self.revisions[self.current];
+        let can_merge = match self.grouping {
+            true => self.group_revision == Some(self.current),
+            false => insert_timeout.is_some_and(|timeout| {
+                self.current != 0
+                    && current.mergeable
+                    && current.last_child.is_none()
+                    && now.duration_since(current.timestamp) < timeout
+            }),
+        };
+        if can_merge {
+            current.transaction = std::mem::take(&mut current.transaction).compose(transaction);
+            current.inversion = inversion.compose(std::mem::take(&mut current.inversion));
+            current.timestamp = now;
+            return;
+        }
+
+        let revision = self.revisions.len();
+        if self.grouping {
+            self.group_revision = Some(revision);
+        }
+        self.revisions[self.current].last_child = Some(revision);
+        self.revisions.push(Revision {
+            parent: self.current,
+            last_child: None,
+            transaction,
+            inversion,
+            cursor_before,
+            cursor_after: cursor_before,
+            timestamp: now,
+            mergeable: insert_timeout.is_some() && !self.grouping,
+        });
+        self.current = revision;
+    }
+
+    // stops further edits from being merged into the current revision
+    pub fn seal(&mut self) {
+        self.revisions[self.current].mergeable = false;
+    }
+
+    // commits until end_group are folded into a single new revision
+    pub fn begin_group(&mut self) {
+        self.seal();
+        self.grouping = true;
+        self.group_revision = None;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = false;
+        self.group_revision = None;
+    }
+
+    // true when the changes of `revision` are part of the current text
+    pub fn is_applied(&self, revision: usize) -> bool {
+        let mut current = self.current;
+        // parents always have a lower index than their children
+        while current > revision {
+            current = self.revisions[current].parent;
+        }
+        current == revision
+    }
+
+    pub fn undo_step(&self) -> Option<Step> {
+        match self.current {
+            0 => None,
+            current => Some(Step::Undo(current)),
+        }
+    }
+
+    pub fn redo_step(&self) -> Option<Step> {
+        self.revisions[self.current].last_child.map(Step::Redo)
+    }
+
+    // moves along the tree, returns the transaction to apply and where the cursor goes.
+    // `cursor` is where the cursor is right now, remembered for a later redo
+    pub fn step(&mut self, step: Step, cursor: Pos) -> (&TranSaction, Pos) {
+        match step {
+            Step::Undo(revision) => {
+                debug_assert_eq!(revision, self.current);
+                let parent = self.revisions[revision].parent;
+                self.revisions[parent].last_child = Some(revision);
+                self.current = parent;
+                let revision = &mut self.revisions[revision];
+                revision.mergeable = false;
+                revision.cursor_after = cursor;
+                (&revision.inversion, revision.cursor_before)
+            }
+            Step::Redo(revision) => {
+                debug_assert_eq!(self.revisions[revision].parent, self.current);
+                self.revisions[self.current].last_child = Some(revision);
+                self.current = revision;
+                let revision = &self.revisions[revision];
+                (&revision.transaction, revision.cursor_after)
+            }
+        }
+    }
+
+    // steps needed to get from the current revision to `target`, possibly across branches
+    pub fn path_to(&self, target: usize) -> Vec<Step> {
+        let ancestors = |mut revision: usize| {
+            let mut path = vec![revision];
+            while revision != 0 {
+                revision = self.revisions[revision].parent;
+                path.push(revision);
+            }
+            path
+        };
+        let up = ancestors(self.current);
+        let mut down = ancestors(target);
+        // strip the shared part of both paths, leaving the way up to the common ancestor
+        // and the way down from it
+        let common = up.iter().find(|revision| down.contains(revision)).copied();
+        let common = common.unwrap_or(0);
+
+        let mut steps: Vec<Step> = up
+            .into_iter()
+            .take_while(|revision| *revision != common)
+            .map(Step::Undo)
+            .collect();
+        down.truncate(
+            down.iter()
+                .position(|revision| *revision == common)
+                .unwrap_or(0),
+        );
+        steps.extend(down.into_iter().rev().map(Step::Redo));
+        steps
+    }
+
+    pub fn earlier(&self, kind: UndoKind) -> Vec<Step> {
+        let target = match kind {
+            UndoKind::Steps(n) => self.current.saturating_sub(n),
+            UndoKind::TimePeriod(period) => {
+                let timestamp = self.revisions[self.current].timestamp;
+                let limit = timestamp.checked_sub(period);
+                // last revision that is at least `period` older than the current one
+                self.revisions[..self.current]
+                    .iter()
+                    .rposition(|revision| limit.is_some_and(|limit| revision.timestamp <= limit))
+                    .unwrap_or(0)
+            }
+        };
+        self.path_to(target)
+    }
+
+    pub fn later(&self, kind: UndoKind) -> Vec<Step> {
+        let last = self.revisions.len() - 1;
+        let target = match kind {
+            UndoKind::Steps(n) => (self.current + n).min(last),
+            UndoKind::TimePeriod(period) => {
+                let limit = self.revisions[self.current].timestamp.checked_add(period);
+                // last revision created within `period` after the current one,
+                // a period too long for an Instant reaches the latest revision
+                self.revisions
+                    .iter()
+                    .rposition(|revision| limit.is_none_or(|limit| revision.timestamp <= limit))
+                    .unwrap_or(last)
+                    .max(self.current)
+            }
+        };
+        self.path_to(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a document reduced to its text and history
+    struct Doc {
+        text: Rope,
+        history: History,
+    }
+
+    impl Doc {
+        fn new(text: &str) -> Self {
+            Self {
+                text: Rope::from(text),
+                history: History::default(),
+            }
+        }
+
+        fn insert(&mut self, idx: usize, text: &str, insert_timeout: Option<Duration>) {
+            let transaction = TranSaction::insert(&self.text, idx, text);
+            let original = self.text.clone();
+            assert!(transaction.apply(&mut self.text));
+            let cursor = Pos { y: 0, x: idx };
+            self.history
+                .commit(transaction, &original, cursor, insert_timeout);
+        }
+
+        fn walk(&mut self, steps: Vec<Step>) {
+            for step in steps {
+                let (transaction, _) = self.history.step(step, Pos::default());
+                assert!(transaction.apply(&mut self.text));
+            }
+        }
+
+        fn undo(&mut self) -> bool {
+            let step = self.history.undo_step();
+            self.walk(step.into_iter().collect());
+            step.is_some()
+        }
+
+        fn redo(&mut self) -> bool {
+            let step = self.history.redo_step();
+            self.walk(step.into_iter().collect());
+            step.is_some()
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut doc = Doc::new("abc");
+        doc.insert(3, "d", None);
+        doc.insert(0, "_", None);
+        assert_eq!(doc.text, "_abcd");
+        assert!(doc.undo());
+        assert_eq!(doc.text, "abcd");
+        assert!(doc.undo());
+        assert_eq!(doc.text, "abc");
+        assert!(!doc.undo());
+        assert!(doc.redo());
+        assert!(doc.redo());
+        assert_eq!(doc.text, "_abcd");
+        assert!(!doc.redo());
+    }
+
+    #[test]
+    fn cursor_comes_back_with_undo() {
+        let mut doc = Doc::new("abc");
+        doc.insert(2, "x", None);
+        let (_, cursor) = doc.history.step(Step::Undo(1), Pos { y: 0, x: 3 });
+        assert_eq!(cursor.x, 2);
+        let (_, cursor) = doc.history.step(Step::Redo(1), Pos::default());
+        assert_eq!(cursor.x, 3);
+    }
+
+    #[test]
+    fn editing_after_undo_branches() {
+        let mut doc = Doc::new("");
+        doc.insert(0, "a", None); // 1
+        doc.insert(1, "b", None); // 2
+        doc.undo();
+        doc.insert(1, "c", None); // 3, a sibling of 2
+        assert_eq!(doc.text, "ac");
+        assert_eq!(doc.history.revision_count(), 4);
+        assert!(!doc.history.is_applied(2));
+        assert!(doc.history.is_applied(1));
+
+        // redo follows the newest branch, the old one is still reachable
+        doc.undo();
+        doc.redo();
+        assert_eq!(doc.text, "ac");
+        doc.walk(doc.history.path_to(2));
+        assert_eq!(doc.text, "ab");
+        assert_eq!(doc.history.current_revision(), 2);
+        // and redo now follows the branch that was visited last
+        doc.undo();
+        doc.redo();
+        assert_eq!(doc.text, "ab");
+    }
+
+    #[test]
+    fn earlier_and_later_walk_creation_order() {
+        let mut doc = Doc::new("");
+        doc.insert(0, "a", None); // 1
+        doc.insert(1, "b", None); // 2
+        doc.undo();
+        doc.insert(1, "c", None); // 3
+        // earlier goes back by creation, which crosses into the other branch
+        doc.walk(doc.history.earlier(UndoKind::Steps(1)));
+        assert_eq!(doc.text, "ab");
+        doc.walk(doc.history.earlier(UndoKind::Steps(5)));
+        assert_eq!(doc.text, "");
+        doc.walk(doc.history.later(UndoKind::Steps(2)));
+        assert_eq!(doc.text, "ab");
+        doc.walk(doc.history.later(UndoKind::Steps(5)));
+        assert_eq!(doc.text, "ac");
+    }
+
+    #[test]
+    fn earlier_and_later_by_time() {
+        let mut doc = Doc::new("");
+        doc.insert(0, "a", None);
+        doc.insert(1, "b", None);
+        doc.insert(2, "c", None);
+        let start = doc.history.revisions[0].timestamp;
+        for (revision, seconds) in [(1, 10), (2, 20), (3, 30)] {
+            doc.history.revisions[revision].timestamp = start + Duration::from_secs(seconds);
+        }
+        doc.walk(
+            doc.history
+                .earlier(UndoKind::TimePeriod(Duration::from_secs(15))),
+        );
+        assert_eq!(doc.text, "a");
+        doc.walk(
+            doc.history
+                .earlier(UndoKind::TimePeriod(Duration::from_secs(60))),
+        );
+        assert_eq!(doc.text, "");
+        doc.walk(
+            doc.history
+                .later(UndoKind::TimePeriod(Duration::from_secs(25))),
+        );
+        assert_eq!(doc.text, "ab");
+        // a period an Instant can not hold reaches the latest revision
+        doc.walk(doc.history.later(UndoKind::TimePeriod(Duration::MAX)));
+        assert_eq!(doc.text, "abc");
+    }
+
+    #[test]
+    fn groups_become_one_revision() {
+        let mut doc = Doc::new("");
+        doc.insert(0, "x", None);
+        doc.history.begin_group();
+        doc.insert(1, "a", None);
+        doc.insert(2, "b", Some(Duration::from_secs(60)));
+        doc.insert(0, "c", None);
+        doc.history.end_group();
+        doc.insert(4, "d", None);
+        assert_eq!(doc.text, "cxabd");
+        assert_eq!(doc.history.revision_count(), 4);
+        doc.undo();
+        doc.undo();
+        assert_eq!(doc.text, "x");
+        doc.redo();
+        assert_eq!(doc.text, "cxab");
+    }
+
+    #[test]
+    fn insert_mode_edits_merge_until_sealed() {
+        let timeout = Some(Duration::from_secs(60));
+        let mut doc = Doc::new("");
+        doc.insert(0, "a", timeout);
+        doc.insert(1, "b", timeout);
+        doc.history.seal();
+        doc.insert(2, "c", timeout);
+        // edits outside insert mode never merge
+        doc.insert(3, "d", None);
+        doc.insert(4, "e", None);
+        assert_eq!(doc.history.revision_count(), 5);
+        doc.undo();
+        doc.undo();
+        doc.undo();
+        assert_eq!(doc.text, "ab");
+        doc.undo();
+        assert_eq!(doc.text, "");
+    }
+
+    #[test]
+    fn undone_revisions_do_not_merge() {
+        let timeout = Some(Duration::from_secs(60));
+        let mut doc = Doc::new("");
+        doc.insert(0, "a", timeout);
+        doc.insert(1, "b", timeout);
+        doc.undo();
+        doc.redo();
+        doc.insert(2, "c", timeout);
+        assert_eq!(doc.history.revision_count(), 3);
+    }
+}
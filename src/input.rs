@@ -1,128 +1,622 @@
-use crate::snippets::function::{self, EntityState, SnippetCommand};
+use crate::events::AppEvent;
+use crate::keymap::{Command, Key, KeyMatch, Keymap};
+use crate::snippets::function::{self, EntityMotion, Uncollect};
 use crate::snippets::snippet::SnippetState;
 use crate::{
-    cursor::CursorDirection,
-    editor::{Document, Mode},
+    cmdline::Completion,
+    command,
+    cursor::CursorRange,
+    editor::{Deletion, Document, Mode, PasteAt, Prompt},
+    motion::Motion,
+    pending::PendingChar,
+    register, search, substitute, textobject,
 };
 use crossterm::ExecutableCommand;
 use crossterm::cursor::SetCursorStyle;
-use crossterm::event::{self, Event, KeyCode};
-use ratatui::DefaultTerminal;
-use std::{
-    io::{self, Error},
-    time::Duration,
-};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{DefaultTerminal, layout::Rect};
+use std::io;
 
-macro_rules! one_of {
-    ($key:expr, [$($ch:expr),*], $action:block) => {
-        match $key {
-            $(KeyCode::Char($ch))|* => $action,
-            _ => {}
-        }
-    };
+const FINISH_PART: &str = "Space finishes this part of the function";
+
+enum Resolved {
+    Command(Command),
+    Pending,
+    Unbound(Vec<Key>), // keys that did not make up any binding, typed as text if possible
 }
 
-impl TryFrom<KeyCode> for CursorDirection {
-    type Error = io::Error;
-    fn try_from(value: KeyCode) -> Result<Self, Self::Error> {
-        match value {
-            KeyCode::Char('h') => Ok(CursorDirection::Left),
-            KeyCode::Char('k') => Ok(CursorDirection::Up),
-            KeyCode::Char('j') => Ok(CursorDirection::Down),
-            KeyCode::Char('l') => Ok(CursorDirection::Right),
-            _ => Err(Error::new(
-                io::ErrorKind::InvalidInput,
-                "invalid cursor direction",
-            )),
+// feeds a key into the pending sequence of the current mode
+fn resolve(doc: &mut Document, key: Key) -> Resolved {
+    doc.pending.push_key(key);
+    match Keymap::lookup(doc.keymap.bindings(doc.mode), &doc.pending.sequence) {
+        KeyMatch::Command(command) => Resolved::Command(command),
+        KeyMatch::Pending => Resolved::Pending,
+        KeyMatch::None => Resolved::Unbound(std::mem::take(&mut doc.pending.sequence)),
+    }
+}
+
+fn typed_char(key: &Key) -> Option<char> {
+    match key.code {
+        KeyCode::Char(character)
+            if !key
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+        {
+            Some(character)
         }
+        _ => None,
     }
 }
 
-/// Handle input; returns Ok(true) when quit requested.
-pub fn handle_input(
+/// Handles one event of the main loop; returns Ok(true) when quit requested.
+pub fn handle_event(
     doc: &mut Document,
     snippet: &mut function::Snippet,
     terminal: &mut DefaultTerminal,
-    viewport_height: Option<usize>,
+    event: AppEvent,
 ) -> Result<bool, io::Error> {
-    if event::poll(Duration::from_millis(50))? {
-        if let Event::Key(key) = event::read()? {
+    match event {
+        AppEvent::Terminal(Event::Key(key)) if key.kind != KeyEventKind::Release => {
+            if handle_key(doc, snippet, Key::from(key)) {
+                return Ok(true);
+            }
             match doc.mode {
-                Mode::Normal => {
-                    match key.code {
-                        KeyCode::Char('q') => return Ok(true),
-                        KeyCode::Char('i') => {
-                            doc.mode = Mode::Insert;
-                        }
-                        KeyCode::Char('f') => {
-                            // assuming lang is rust
-                            snippet.start(SnippetState::Create, doc);
-                        }
-                        _ => {}
-                    };
-
-                    let keycode = key.code;
-
-                    // used for key combination commands
-                    if let Ok(snippet_state) = SnippetState::try_from(keycode) {
-                        snippet.check_command(SnippetCommand::SnippetState(snippet_state), doc);
-                    }
+                Mode::Normal | Mode::Select | Mode::Preview => terminal
+                    .backend_mut()
+                    .execute(SetCursorStyle::SteadyBlock)?,
+                Mode::Insert | Mode::Command => {
+                    terminal.backend_mut().execute(SetCursorStyle::SteadyBar)?
+                }
+            };
+        }
+        AppEvent::KeyTimeout => {
+            if key_timeout(doc, snippet) {
+                return Ok(true);
+            }
+        }
+        AppEvent::Terminal(Event::Paste(text)) => paste(doc, snippet, &text),
+        AppEvent::Terminal(Event::Resize(width, height)) => {
+            doc.screen = Rect::new(0, 0, width, height);
+        }
+        // leaving the terminal ends the current insert mode undo step
+        AppEvent::Terminal(Event::FocusLost) => doc.history.seal(),
+        AppEvent::Terminal(Event::FocusGained) => doc.check_disk(),
+        AppEvent::FileChanged(path) if doc.path.as_ref() == Some(&path) => doc.check_disk(),
+        // the buffer moved to another file before the change was seen
+        AppEvent::FileChanged(_) => return Ok(false),
+        AppEvent::Terminal(_) => return Ok(false),
+    }
+    snippet.sync(doc);
+    doc.scroll_to_cursor();
+    doc.redraw = true;
+    Ok(false)
+}
 
-                    if let Ok(entity_state) = EntityState::try_from(keycode) {
-                        snippet.check_command(SnippetCommand::EntityState(entity_state), doc);
-                    }
+// returns true when the editor should quit
+fn handle_key(doc: &mut Document, snippet: &mut function::Snippet, key: Key) -> bool {
+    doc.message = None;
 
-                    // free cursor movement will be changed instead to act on snippet entities
-                    if let Ok(cursor_direction) = CursorDirection::try_from(keycode) {
-                        doc.cursor.move_cursor(&doc.editor.text, cursor_direction)
-                    }
+    // esc only drops a half typed command, it is bound to something once nothing is pending
+    if key.code == KeyCode::Esc && key.modifiers.is_empty() && !doc.pending.is_empty() {
+        doc.pending.clear();
+        return false;
+    }
+    doc.pending.record(key);
+    if let Some(awaiting) = doc.pending.awaiting.take() {
+        argument(doc, snippet, awaiting, key);
+        return false;
+    }
+    // digits in front of a command repeat it, a lone 0 is still a motion
+    if matches!(doc.mode, Mode::Normal | Mode::Select)
+        && doc.pending.sequence.is_empty()
+        && let Some(digit) = typed_char(&key).and_then(|c| c.to_digit(10))
+        && (digit != 0 || doc.pending.count.is_some())
+    {
+        doc.pending.push_digit(digit);
+        return false;
+    }
+    match resolve(doc, key) {
+        Resolved::Command(command) => return run_command(command, doc, snippet),
+        Resolved::Pending => {}
+        Resolved::Unbound(keys) => {
+            doc.pending.clear();
+            type_keys(doc, snippet, &keys);
+        }
+    }
+    false
+}
+
+// keys that are no command are text in insert and command mode
+fn type_keys(doc: &mut Document, snippet: &mut function::Snippet, keys: &[Key]) {
+    for character in keys.iter().filter_map(typed_char) {
+        match doc.mode {
+            Mode::Normal | Mode::Select | Mode::Preview => {}
+            Mode::Insert => insert_char(character, doc, snippet),
+            Mode::Command => {
+                doc.completion = None;
+                doc.command_line.insert(character.encode_utf8(&mut [0; 4]));
+                prompt_changed(doc);
+            }
+        }
+    }
+}
+
+// nothing followed the start of a multi key binding in time: a binding that is
+// also the start of longer ones runs now, other keys are typed as they are
+fn key_timeout(doc: &mut Document, snippet: &mut function::Snippet) -> bool {
+    let command = doc
+        .keymap
+        .bindings(doc.mode)
+        .get(&doc.pending.sequence)
+        .and_then(|node| node.command);
+    if let Some(command) = command {
+        return run_command(command, doc, snippet);
+    }
+    let keys = std::mem::take(&mut doc.pending.sequence);
+    doc.pending.clear();
+    type_keys(doc, snippet, &keys);
+    false
+}
+
+// the key a command waited for
+fn argument(doc: &mut Document, snippet: &mut function::Snippet, awaiting: PendingChar, key: Key) {
+    let count = doc.pending.count.unwrap_or(1);
+    match (awaiting, typed_char(&key)) {
+        (PendingChar::Motion(motion), Some(character)) => {
+            move_cursor(doc, motion(character), count)
+        }
+        (PendingChar::Object { around }, _) => select_object(doc, snippet, key, around),
+        (PendingChar::Surround, Some(character)) => surround(doc, character),
+        // the count typed before the register is still used by the command after it
+        (PendingChar::Register, Some(name)) if register::is_valid(name) => {
+            doc.pending.register = Some(name);
+            return;
+        }
+        (PendingChar::InsertRegister, Some(name)) if register::is_valid(name) => {
+            match doc.read_register(name) {
+                Some(yank) => doc.paste(&yank, PasteAt::Cursor),
+                None => doc.message = Some(format!("register {} is empty", name)),
+            }
+        }
+        (PendingChar::Register | PendingChar::InsertRegister, Some(name)) => {
+            doc.message = Some(format!("no register {}", name))
+        }
+        (PendingChar::Action(action), _) => {
+            let command = doc.keymap.entity_command(key);
+            match (action, command, command.and_then(Command::to_entity_state)) {
+                // c f writes a new function at the cursor
+                (SnippetState::Create, Some(Command::EntityFunction), _) => {
+                    snippet.start(SnippetState::Create, doc)
                 }
-                Mode::Insert => match key.code {
-                    KeyCode::Esc => {
-                        doc.mode = Mode::Normal;
-                    }
-                    KeyCode::Char(character) => {
-                        let is_collected = snippet.entity_manager.collect_chunk_buffer(
-                            character,
-                            doc,
-                            &mut snippet.snippet_state,
-                            &mut snippet.notifications,
-                        );
-                        // Insert the character at current cursor position
-                        if is_collected.is_err() {
-                            doc.cursor_pos_insert(&character.to_string());
-                            doc.cursor.pos.x += 1;
-                        }
-                    }
-                    KeyCode::Enter => {
-                        doc.cursor_pos_insert("\n");
-                        doc.cursor.pos.y += 1;
-                        doc.cursor.pos.x = 0;
-                    }
-                    KeyCode::Backspace => {
-                        // TODO can be optimized as start row also checks for newlines
-                        let idx = doc.cursor_get_idx();
-                        let prev_idx = idx.saturating_sub(1);
-                        let cursor_pos = &mut doc.cursor.pos;
-
-                        if !doc.editor.is_start_row(idx) {
-                            doc.editor.text.remove(prev_idx..idx);
-                            cursor_pos.x -= 1;
-                        } else if cursor_pos.y != 0 {
-                            doc.editor.text.remove(prev_idx..idx);
-                            let pos = doc.editor.idx_to_pos(prev_idx);
-                            doc.cursor_set_pos(pos);
-                        }
-                        // todo backspace should not work for deleteion of syntax
-                    }
-                    _ => {}
-                },
+                (_, _, Some(entity_state)) => snippet.act(action, entity_state, doc),
+                _ => doc.message = Some(format!("{} is not an entity key", key)),
+            }
+        }
+        _ => doc.message = Some(format!("{} cancelled", doc.pending.feedback())),
+    }
+    doc.pending.clear();
+}
+
+// fills in the word in front of the cursor, tab again takes the next candidate
+fn complete(doc: &mut Document, backward: bool) {
+    if doc.prompt != Prompt::Command {
+        return;
+    }
+    if let Some(completion) = doc.completion.as_mut() {
+        completion.step(backward);
+        completion.apply(&mut doc.command_line);
+        return;
+    }
+    let (start, candidates) = command::complete(doc.command_line.before_cursor());
+    let completion = Completion::new(start, candidates);
+    match completion.candidates.len() {
+        0 => doc.message = Some("no completions".to_string()),
+        // nothing to choose from, the next tab completes the next word
+        1 => completion.apply(&mut doc.command_line),
+        _ => {
+            completion.apply(&mut doc.command_line);
+            doc.completion = Some(completion);
+        }
+    }
+}
+
+// search moves along while its pattern is typed
+fn prompt_changed(doc: &mut Document) {
+    if doc.prompt == Prompt::Search {
+        search::update(doc);
+    }
+}
+
+// pasted text arrives in one piece and is inserted as one edit
+fn paste(doc: &mut Document, snippet: &mut function::Snippet, text: &str) {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    match doc.mode {
+        // the part of a function being typed only takes typed keys
+        Mode::Insert if snippet.is_trigger_state() => {
+            snippet.notifications = Some(FINISH_PART.to_string())
+        }
+        Mode::Insert => doc.insert_at_cursors(&text),
+        Mode::Command => {
+            doc.command_line
+                .insert(text.lines().next().unwrap_or_default());
+            prompt_changed(doc);
+        }
+        Mode::Normal | Mode::Select | Mode::Preview => {
+            doc.message = Some("paste in insert mode".to_string())
+        }
+    }
+}
+
+// returns true when the editor should quit
+fn run_command(command: Command, doc: &mut Document, snippet: &mut function::Snippet) -> bool {
+    if doc.mode == Mode::Insert
+        && snippet.is_trigger_state()
+        && edit_collected(command, doc, snippet)
+    {
+        doc.pending.clear();
+        return false;
+    }
+    // commands that wait for another key keep everything typed so far
+    if let Some(awaiting) = command.to_pending_char() {
+        doc.pending.wait(awaiting);
+        return false;
+    }
+    let count = doc.pending.count;
+    let register = doc.pending.register.unwrap_or(register::UNNAMED);
+    doc.pending.clear();
+    // tab steps through the candidates, anything else is done with them
+    if !matches!(command, Command::CompleteNext | Command::CompletePrev) {
+        doc.completion = None;
+    }
+    if let Some(motion) = command.to_motion() {
+        match doc.mode {
+            Mode::Command => doc.command_line.move_cursor(motion),
+            // typing somewhere else is a new undo step
+            Mode::Insert => {
+                doc.history.seal();
+                move_cursor(doc, motion, count.unwrap_or(1));
             }
+            // free cursor movement will be changed instead to act on snippet entities
+            _ => move_cursor(doc, motion, count.unwrap_or(1)),
+        }
+        return false;
+    }
+    if let Some(motion) = command.to_entity_motion() {
+        move_to_entity(doc, snippet, motion, count.unwrap_or(1));
+        return false;
+    }
+    match command {
+        Command::Quit => return command::quit(doc),
+        Command::NormalMode => {
             match doc.mode {
-                Mode::Normal => terminal.backend_mut().execute(SetCursorStyle::SteadyBlock),
-                Mode::Insert => terminal.backend_mut().execute(SetCursorStyle::SteadyBar),
+                Mode::Insert => {
+                    // later normal mode edits must not join the creation undo step
+                    snippet.entity_manager.stop(doc, &mut snippet.snippet_state);
+                    doc.history.seal();
+                }
+                Mode::Select => doc.cursor.selection.collapse(),
+                Mode::Command if doc.prompt == Prompt::Search => search::cancel(doc),
+                _ => {}
+            }
+            doc.mode = Mode::Normal;
+        }
+        Command::SelectMode => doc.mode = Mode::Select,
+        Command::DeleteSelection => {
+            doc.delete_selections(register);
+            doc.mode = Mode::Normal;
+        }
+        Command::ChangeSelection => {
+            doc.delete_selections(register);
+            doc.mode = Mode::Insert;
+        }
+        Command::YankSelection => {
+            doc.yank_selections(register);
+            doc.cursor.selection.collapse();
+            doc.mode = Mode::Normal;
+        }
+        Command::PasteAfter | Command::PasteBefore => {
+            let at = match command {
+                Command::PasteAfter => PasteAt::After,
+                _ => PasteAt::Before,
+            };
+            // read once, the clipboard registers run a program for every read
+            let Some(yank) = doc.read_register(register) else {
+                doc.message = Some(format!("register {} is empty", register));
+                return false;
             };
+            for _ in 0..count.unwrap_or(1) {
+                // yanked functions are created again, not pasted as text
+                match yank.functions.is_empty() {
+                    true => doc.paste(&yank, at),
+                    false => snippet.paste_functions(doc, &yank.functions, at),
+                }
+            }
+        }
+        Command::YankFunction => {
+            let Some(yank) = snippet.yank_function(&doc.editor.text, doc.cursor_get_idx()) else {
+                doc.message = Some("cursor is not inside a function".to_string());
+                return false;
+            };
+            if doc.store_register(register, yank, false) {
+                doc.message = Some(format!("yanked function into \"{}", register));
+            }
+        }
+        Command::InsertMode => doc.mode = Mode::Insert,
+        Command::CmdlineMode => {
+            doc.command_line.clear();
+            doc.command_history.reset();
+            doc.prompt = Prompt::Command;
+            doc.mode = Mode::Command;
+        }
+        Command::SearchForward => search::start(doc, false),
+        Command::SearchBackward => search::start(doc, true),
+        Command::SearchNext => search::jump(doc, false, count.unwrap_or(1)),
+        Command::SearchPrev => search::jump(doc, true, count.unwrap_or(1)),
+        Command::HistoryPrev | Command::HistoryNext => {
+            let history = match doc.prompt {
+                Prompt::Command => &mut doc.command_history,
+                Prompt::Search => &mut doc.search.history,
+            };
+            if let Some(entry) = history.browse(command == Command::HistoryPrev) {
+                let entry = entry.to_string();
+                doc.command_line.set(entry);
+                prompt_changed(doc);
+            }
+        }
+        Command::CompleteNext | Command::CompletePrev => {
+            complete(doc, command == Command::CompletePrev)
+        }
+        Command::AcceptChange => substitute::accept(doc),
+        Command::SkipChange => substitute::skip(doc),
+        Command::AcceptAll => substitute::accept_all(doc),
+        Command::StopChanges => substitute::finish(doc),
+        Command::CancelChanges => substitute::cancel(doc),
+        Command::Undo => doc.undo(),
+        Command::Redo => doc.redo(),
+        Command::CreateFunction => {
+            // assuming lang is rust
+            snippet.start(SnippetState::Create, doc);
+        }
+        Command::HalfPageDown | Command::HalfPageUp => {
+            let rows = (doc.viewport.height as usize / 2).max(1);
+            let motion = match command {
+                Command::HalfPageDown => Motion::Down,
+                _ => Motion::Up,
+            };
+            let tab_width = doc.settings.editor.tab_width;
+            doc.cursor
+                .scroll_rows(&doc.editor.text, rows, motion, tab_width);
+        }
+        Command::GotoFirstLine => move_cursor(
+            doc,
+            count.map_or(Motion::DocumentStart, Motion::GotoLine),
+            1,
+        ),
+        Command::GotoLastLine => {
+            move_cursor(doc, count.map_or(Motion::DocumentEnd, Motion::GotoLine), 1)
+        }
+        Command::CenterCursor => doc.cursor.center(doc.viewport.height as usize),
+        Command::AddCursorNextMatch => doc.add_cursor_next_match(),
+        Command::AddCursorsInEntity => {
+            let Some(span) = snippet.entity_span_at(doc.cursor_get_idx()) else {
+                doc.message = Some("cursor is not inside an entity".to_string());
+                return false;
+            };
+            if doc.select_word_occurrences(span) == 0 {
+                doc.message = Some("no word under the cursor".to_string());
+            }
+        }
+        Command::KeepPrimaryCursor => {
+            doc.cursor.selection.keep_primary();
+            doc.cursor.sync_pos(&doc.editor.text);
+        }
+        Command::InsertNewline => {
+            doc.newline_at_cursors(|text, idx| snippet.indents_body(text, idx))
+        }
+        Command::InsertIndent => doc.indent_at_cursors(),
+        Command::DeleteCharBackward => match doc.mode {
+            Mode::Command if doc.command_line.is_empty() => {
+                return run_command(Command::NormalMode, doc, snippet);
+            }
+            Mode::Command => {
+                doc.command_line.delete_backward();
+                prompt_changed(doc);
+            }
+            _ => delete(doc, snippet, Deletion::CharBackward),
+        },
+        Command::DeleteCharForward | Command::DeleteWordBackward | Command::DeleteToLineStart
+            if doc.mode == Mode::Command =>
+        {
+            match command {
+                Command::DeleteCharForward => doc.command_line.delete_forward(),
+                Command::DeleteWordBackward => doc.command_line.delete_word_backward(),
+                _ => doc.command_line.delete_to_start(),
+            }
+            prompt_changed(doc);
+        }
+        Command::DeleteCharForward => delete(doc, snippet, Deletion::CharForward),
+        Command::DeleteWordBackward => delete(doc, snippet, Deletion::WordBackward),
+        Command::DeleteToLineStart => delete(doc, snippet, Deletion::ToLineStart),
+        Command::ExecuteCmdline => {
+            doc.mode = Mode::Normal;
+            if doc.prompt == Prompt::Search {
+                search::confirm(doc);
+                return false;
+            }
+            let line = doc.command_line.take();
+            doc.command_history.push(line.clone());
+            let quit = command::execute(doc, snippet, &line);
+            if let Err(e) = doc.command_history.save()
+                && doc.message.is_none()
+            {
+                doc.message = Some(format!("command history not saved: {}", e));
+            }
+            return quit;
         }
+        // entities only mean something right after an action
+        _ => {}
+    }
+    false
+}
+
+fn move_cursor(doc: &mut Document, motion: Motion, count: usize) {
+    let tab_width = doc.settings.editor.tab_width;
+    let extend = doc.mode == Mode::Select;
+    doc.cursor
+        .move_cursor(&doc.editor.text, motion, count, tab_width, extend);
+}
+
+// selects a text object at every cursor: a delimiter pair or an entity from the
+// entity layer, cursors without one around them keep their selection
+fn select_object(doc: &mut Document, snippet: &function::Snippet, key: Key, around: bool) {
+    let command = doc.keymap.entity_command(key);
+    let pair = typed_char(&key).and_then(textobject::pair_for);
+    let text = &doc.editor.text;
+    let object = |idx: usize| match (pair, command) {
+        (Some(pair), _) => textobject::pair_range(text, idx, pair, around),
+        (None, Some(Command::EntityFunction)) => snippet.function_object(text, idx, around),
+        (None, Some(command)) => {
+            let entity_state = command.to_entity_state()?;
+            snippet.component_object(idx, entity_state, around)
+        }
+        (None, None) => None,
+    };
+    let mut found = 0;
+    doc.cursor
+        .selection
+        .transform(|range| match object(range.head) {
+            Some(object) => {
+                found += 1;
+                CursorRange::new(object.start, object.end)
+            }
+            None => range,
+        });
+    doc.cursor.sync_pos(text);
+    if found == 0 {
+        doc.message = Some(format!("no {} object around the cursor", key));
+    }
+}
+
+fn surround(doc: &mut Document, character: char) {
+    let Some(pair) = textobject::pair_for(character) else {
+        doc.message = Some(format!("no delimiter pair for {}", character));
+        return;
+    };
+    doc.surround_selections(pair.open, pair.close);
+    doc.cursor.selection.collapse();
+    doc.mode = Mode::Normal;
+}
+
+// every cursor jumps on its own, a cursor with nowhere to go stays put
+fn move_to_entity(
+    doc: &mut Document,
+    snippet: &function::Snippet,
+    motion: EntityMotion,
+    count: usize,
+) {
+    let text = &doc.editor.text;
+    let primary = doc.cursor.selection.primary().head;
+    doc.cursor.selection.transform(|range| {
+        let mut idx = range.head;
+        for _ in 0..count {
+            match snippet.entity_motion(text, idx, motion) {
+                Some(target) => idx = target,
+                None => break,
+            }
+        }
+        CursorRange::point(idx)
+    });
+    doc.cursor.sync_pos(text);
+    if doc.cursor.selection.primary().head == primary {
+        doc.message = Some("no entity in that direction".to_string());
+    }
+}
+
+// backspace and friends leave the syntax of functions alone. A backspace that
+// only runs into syntax moves on to the end of the value in front of it instead
+fn delete(doc: &mut Document, snippet: &mut function::Snippet, deletion: Deletion) {
+    let backward = deletion != Deletion::CharForward;
+    let mut blocked = Vec::new();
+    let ranges: Vec<_> = doc
+        .deletion_ranges(deletion)
+        .into_iter()
+        .filter_map(|range| {
+            let allowed = snippet.guard_deletion(range.clone(), backward);
+            if allowed.as_ref() != Some(&range) {
+                blocked.push(range.end);
+            }
+            allowed
+        })
+        .collect();
+    if blocked.is_empty() {
+        doc.delete_ranges(ranges);
+        return;
+    }
+    snippet.notifications = Some("Syntax of a function can not be deleted".to_string());
+    if deletion == Deletion::CharBackward {
+        let mut jumped = false;
+        doc.cursor
+            .selection
+            .transform(|range| match blocked.contains(&range.head) {
+                true => match snippet.prev_value_end(range.head) {
+                    Some(end) => {
+                        jumped = true;
+                        CursorRange::point(end)
+                    }
+                    None => range,
+                },
+                false => range,
+            });
+        doc.cursor.sync_pos(&doc.editor.text);
+        if jumped {
+            snippet.notifications = Some(
+                "Syntax of a function can not be deleted, moved to the value before it".to_string(),
+            );
+        }
+    }
+    doc.delete_ranges(ranges);
+}
+
+// insert mode editing while a function is created part by part: what was typed
+// for the current part can be taken back, keys that would leave it are refused.
+// Returns false for commands that do not touch the text
+fn edit_collected(command: Command, doc: &mut Document, snippet: &mut function::Snippet) -> bool {
+    let amount = match command {
+        Command::DeleteCharBackward => Uncollect::Char,
+        Command::DeleteWordBackward => Uncollect::Word,
+        Command::DeleteToLineStart => Uncollect::All,
+        // registers are pasted without going through the collected part
+        Command::InsertNewline
+        | Command::InsertIndent
+        | Command::InsertRegister
+        | Command::DeleteCharForward => {
+            snippet.notifications = Some(FINISH_PART.to_string());
+            return true;
+        }
+        _ if command.to_motion().is_some() => {
+            snippet.notifications = Some(FINISH_PART.to_string());
+            return true;
+        }
+        _ => return false,
+    };
+    let _ = snippet.entity_manager.uncollect(
+        amount,
+        doc,
+        &mut snippet.snippet_state,
+        &mut snippet.notifications,
+    );
+    true
+}
+
+fn insert_char(character: char, doc: &mut Document, snippet: &mut function::Snippet) {
+    let is_collected = snippet.entity_manager.collect_chunk_buffer(
+        character,
+        doc,
+        &mut snippet.snippet_state,
+        &mut snippet.notifications,
+    );
+    // Insert the character at every cursor
+    if is_collected.is_err() {
+        doc.insert_at_cursors(&character.to_string());
     }
-    Ok(false)
 }
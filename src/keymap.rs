@@ -0,0 +1,858 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use strum::{Display, EnumMessage, EnumString};
+
+use crate::{
+    editor::Mode,
+    motion::Motion,
+    pending::PendingChar,
+    settings::KeySettings,
+    snippets::{
+        function::{EntityMotion, EntityState},
+        snippet::SnippetState,
+    },
+};
+
+// everything a key can be bound to, the snake_case name is used in config files
+// the message is shown in the which-key popup, the name is used without one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, EnumMessage)]
+#[strum(serialize_all = "snake_case")]
+pub enum Command {
+    NoOp, // bind a key to this to unbind it
+    Quit,
+    NormalMode,
+    InsertMode,
+    CmdlineMode,
+    SelectMode,
+    Undo,
+    Redo,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveWordNext,
+    MoveWordPrev,
+    MoveWordEnd,
+    MoveBigWordNext,
+    MoveBigWordPrev,
+    MoveBigWordEnd,
+    MoveLineStart,
+    MoveFirstNonBlank,
+    MoveLineEnd,
+    MoveParagraphNext,
+    MoveParagraphPrev,
+    GotoFirstLine, // or the line given as count
+    GotoLastLine,
+    // follow the snippet entities instead of the text
+    NextFunction,
+    PrevFunction,
+    NextComponent,
+    PrevComponent,
+    NextParam,
+    PrevParam,
+    // wait for the char to look for in the line
+    FindNextChar,
+    TillNextChar,
+    FindPrevChar,
+    TillPrevChar,
+    CreateFunction,
+    HalfPageDown,
+    HalfPageUp,
+    CenterCursor,
+    // multiple cursors
+    AddCursorNextMatch,
+    AddCursorsInEntity, // every occurrence of the word under the cursor in its function
+    KeepPrimaryCursor,
+    // select mode, text objects wait for an entity key or a delimiter
+    SelectInside,
+    SelectAround,
+    DeleteSelection,
+    ChangeSelection,
+    YankSelection,
+    SurroundSelection, // waits for the delimiter to put around
+    // search
+    SearchForward,
+    SearchBackward,
+    SearchNext, // in the direction of the last search
+    SearchPrev,
+    HistoryPrev, // older entry in the prompt history
+    HistoryNext,
+    // registers
+    SelectRegister, // waits for the register name the next command uses
+    PasteAfter,
+    PasteBefore,
+    YankFunction,   // with its values, so it pastes as a function again
+    InsertRegister, // waits for the register name, then pastes
+    // substitution preview, confirm mode answers one replacement at a time
+    AcceptChange,
+    SkipChange,
+    AcceptAll,
+    StopChanges, // keeps what was accepted so far
+    CancelChanges,
+    // actions, wait for an entity key
+    #[strum(message = "create")]
+    ActionCreate,
+    #[strum(message = "delete")]
+    ActionDelete,
+    #[strum(message = "swap, delete and insert")]
+    ActionSwap,
+    #[strum(message = "refactor")]
+    ActionRefactor,
+    // entities, only looked up right after an action
+    #[strum(message = "name")]
+    EntityName,
+    #[strum(message = "parameters")]
+    EntityParam,
+    #[strum(message = "result type")]
+    EntityResult,
+    #[strum(message = "body")]
+    EntityBody,
+    #[strum(message = "whole function")]
+    EntityFunction, // a text object, and what `c f` creates
+    // insert and command mode editing
+    InsertNewline,
+    InsertIndent,
+    DeleteCharBackward,
+    DeleteCharForward,
+    DeleteWordBackward,
+    DeleteToLineStart,
+    ExecuteCmdline,
+    CompleteNext, // tab completion on the command line
+    CompletePrev,
+}
+
+impl Command {
+    pub fn description(self) -> String {
+        match self.get_message() {
+            Some(message) => message.to_string(),
+            None => self.to_string().replace('_', " "),
+        }
+    }
+
+    // a count repeats these motions
+    pub fn to_motion(self) -> Option<Motion> {
+        match self {
+            Command::MoveLeft => Some(Motion::Left),
+            Command::MoveRight => Some(Motion::Right),
+            Command::MoveUp => Some(Motion::Up),
+            Command::MoveDown => Some(Motion::Down),
+            Command::MoveWordNext => Some(Motion::WordNext),
+            Command::MoveWordPrev => Some(Motion::WordPrev),
+            Command::MoveWordEnd => Some(Motion::WordEnd),
+            Command::MoveBigWordNext => Some(Motion::BigWordNext),
+            Command::MoveBigWordPrev => Some(Motion::BigWordPrev),
+            Command::MoveBigWordEnd => Some(Motion::BigWordEnd),
+            Command::MoveLineStart => Some(Motion::LineStart),
+            Command::MoveFirstNonBlank => Some(Motion::FirstNonBlank),
+            Command::MoveLineEnd => Some(Motion::LineEnd),
+            Command::MoveParagraphNext => Some(Motion::ParagraphNext),
+            Command::MoveParagraphPrev => Some(Motion::ParagraphPrev),
+            _ => None,
+        }
+    }
+
+    pub fn to_entity_motion(self) -> Option<EntityMotion> {
+        match self {
+            Command::NextFunction => Some(EntityMotion::NextFunction),
+            Command::PrevFunction => Some(EntityMotion::PrevFunction),
+            Command::NextComponent => Some(EntityMotion::NextComponent),
+            Command::PrevComponent => Some(EntityMotion::PrevComponent),
+            Command::NextParam => Some(EntityMotion::NextParam),
+            Command::PrevParam => Some(EntityMotion::PrevParam),
+            _ => None,
+        }
+    }
+
+    pub fn to_char_motion(self) -> Option<fn(char) -> Motion> {
+        match self {
+            Command::FindNextChar => Some(Motion::FindNext),
+            Command::TillNextChar => Some(Motion::TillNext),
+            Command::FindPrevChar => Some(Motion::FindPrev),
+            Command::TillPrevChar => Some(Motion::TillPrev),
+            _ => None,
+        }
+    }
+
+    // commands that wait for one more key before they do anything
+    pub fn to_pending_char(self) -> Option<PendingChar> {
+        if let Some(motion) = self.to_char_motion() {
+            return Some(PendingChar::Motion(motion));
+        }
+        if let Some(action) = self.to_snippet_state() {
+            return Some(PendingChar::Action(action));
+        }
+        match self {
+            Command::SelectInside => Some(PendingChar::Object { around: false }),
+            Command::SelectAround => Some(PendingChar::Object { around: true }),
+            Command::SurroundSelection => Some(PendingChar::Surround),
+            Command::SelectRegister => Some(PendingChar::Register),
+            Command::InsertRegister => Some(PendingChar::InsertRegister),
+            _ => None,
+        }
+    }
+
+    pub fn to_snippet_state(self) -> Option<SnippetState> {
+        match self {
+            Command::ActionCreate => Some(SnippetState::Create),
+            Command::ActionDelete => Some(SnippetState::Delete),
+            Command::ActionSwap => Some(SnippetState::Swap),
+            Command::ActionRefactor => Some(SnippetState::Refactor),
+            _ => None,
+        }
+    }
+
+    pub fn to_entity_state(self) -> Option<EntityState> {
+        match self {
+            Command::EntityName => Some(EntityState::Name),
+            Command::EntityParam => Some(EntityState::Param),
+            Command::EntityResult => Some(EntityState::Result),
+            Command::EntityBody => Some(EntityState::Body),
+            _ => None,
+        }
+    }
+}
+
+// key without the event kind/state noise, shift is part of the char itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        let mut modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        // backtab is shift+tab already
+        if !matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers |= event.modifiers & KeyModifiers::SHIFT;
+        }
+        Self {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+impl Key {
+    // "h", "G", "C-r", "A-x", "S-tab", "esc", "space" ...
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+            modifiers |= match &rest[..1] {
+                "C" => KeyModifiers::CONTROL,
+                "A" => KeyModifiers::ALT,
+                "S" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier in \"{}\"", text)),
+            };
+            rest = &rest[2..];
+        }
+        let code = match rest.to_lowercase().as_str() {
+            "esc" => KeyCode::Esc,
+            "enter" | "ret" => KeyCode::Enter,
+            "backspace" => KeyCode::Backspace,
+            "del" | "delete" => KeyCode::Delete,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "space" => KeyCode::Char(' '),
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("unknown key \"{}\"", text)),
+                }
+            }
+        };
+        // the same as what From<KeyEvent> makes of the terminal's key events
+        let code = match code {
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            code => code,
+        };
+        if let KeyCode::Char(_) | KeyCode::BackTab = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "A-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            // crossterm writes "Back Tab" and "Page Up", Key::parse reads them without spaces
+            code => write!(f, "{}", code.to_string().to_lowercase().replace(' ', "")),
+        }
+    }
+}
+
+// "g g" is g followed by g
+pub fn parse_sequence(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text
+        .split_whitespace()
+        .map(Key::parse)
+        .collect::<Result<Vec<Key>, String>>()?;
+    match keys.is_empty() {
+        true => Err("empty key binding".to_string()),
+        false => Ok(keys),
+    }
+}
+
+pub fn sequence_to_string(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| key.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub type Bindings = HashMap<Vec<Key>, Command>;
+
+/// Bindings of a layer as a tree with one level per key, the keys typed so far
+/// lead straight to everything that may still follow them. A binding that is
+/// also the start of longer ones runs when the key timeout passes.
+#[derive(Default)]
+pub struct KeyTrie {
+    pub command: Option<Command>,
+    pub children: HashMap<Key, KeyTrie>,
+}
+
+impl KeyTrie {
+    fn new(bindings: &Bindings) -> Self {
+        let mut root = Self::default();
+        for (keys, command) in bindings {
+            let node = keys.iter().fold(&mut root, |node, key| {
+                node.children.entry(*key).or_default()
+            });
+            node.command = Some(*command);
+        }
+        root
+    }
+
+    // the node the keys lead to
+    pub fn get(&self, keys: &[Key]) -> Option<&KeyTrie> {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+}
+
+pub enum KeyMatch {
+    Command(Command),
+    Pending, // keys so far are the start of a longer binding
+    None,
+}
+
+pub struct Keymap {
+    pub normal: KeyTrie,
+    pub insert: KeyTrie,
+    pub command: KeyTrie,
+    pub select: KeyTrie,
+    pub preview: KeyTrie,
+    pub entity: KeyTrie, // looked up after an action or text object, picks what it applies to
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_layers(default_layers())
+    }
+}
+
+// the built in bindings of every layer, by the name used in config files
+fn default_layers() -> [(&'static str, Bindings); 6] {
+    use Command::*;
+    // written the same way as in config files
+    let bindings = |list: &[(&str, Command)]| -> Bindings {
+        list.iter()
+            .map(|(keys, cmd)| (parse_sequence(keys).expect("valid default key"), *cmd))
+            .collect()
+    };
+    // moving in select mode extends the selections
+    let motions = [
+        ("h", MoveLeft),
+        ("l", MoveRight),
+        ("k", MoveUp),
+        ("j", MoveDown),
+        ("w", MoveWordNext),
+        ("b", MoveWordPrev),
+        ("e", MoveWordEnd),
+        ("W", MoveBigWordNext),
+        ("B", MoveBigWordPrev),
+        ("E", MoveBigWordEnd),
+        ("0", MoveLineStart),
+        ("^", MoveFirstNonBlank),
+        ("$", MoveLineEnd),
+        ("}", MoveParagraphNext),
+        ("{", MoveParagraphPrev),
+        ("g g", GotoFirstLine),
+        ("G", GotoLastLine),
+        ("] f", NextFunction),
+        ("[ f", PrevFunction),
+        ("] e", NextComponent),
+        ("[ e", PrevComponent),
+        ("] p", NextParam),
+        ("[ p", PrevParam),
+        ("f", FindNextChar),
+        ("t", TillNextChar),
+        ("F", FindPrevChar),
+        ("T", TillPrevChar),
+    ];
+    [
+        (
+            "normal",
+            bindings(
+                &[
+                    &motions[..],
+                    &[
+                        ("q", Quit),
+                        ("i", InsertMode),
+                        ("v", SelectMode),
+                        (":", CmdlineMode),
+                        ("u", Undo),
+                        ("C-r", Redo),
+                        ("C-d", HalfPageDown),
+                        ("C-u", HalfPageUp),
+                        ("z z", CenterCursor),
+                        ("C-n", AddCursorNextMatch),
+                        ("C-e", AddCursorsInEntity),
+                        ("esc", KeepPrimaryCursor),
+                        ("c", ActionCreate),
+                        ("d", ActionDelete),
+                        ("s", ActionSwap),
+                        ("y", YankSelection),
+                        ("p", PasteAfter),
+                        ("P", PasteBefore),
+                        ("Y", YankFunction),
+                        ("/", SearchForward),
+                        ("?", SearchBackward),
+                        ("n", SearchNext),
+                        ("N", SearchPrev),
+                        ("\"", SelectRegister),
+                    ],
+                ]
+                .concat(),
+            ),
+        ),
+        (
+            "insert",
+            bindings(&[
+                ("esc", NormalMode),
+                ("enter", InsertNewline),
+                ("tab", InsertIndent),
+                ("backspace", DeleteCharBackward),
+                ("del", DeleteCharForward),
+                ("C-w", DeleteWordBackward),
+                ("C-u", DeleteToLineStart),
+                ("left", MoveLeft),
+                ("right", MoveRight),
+                ("up", MoveUp),
+                ("down", MoveDown),
+                ("home", MoveLineStart),
+                ("end", MoveLineEnd),
+                ("C-r", InsertRegister),
+            ]),
+        ),
+        (
+            "command",
+            bindings(&[
+                ("esc", NormalMode),
+                ("enter", ExecuteCmdline),
+                ("backspace", DeleteCharBackward),
+                ("del", DeleteCharForward),
+                ("C-w", DeleteWordBackward),
+                ("C-u", DeleteToLineStart),
+                ("left", MoveLeft),
+                ("right", MoveRight),
+                ("C-left", MoveWordPrev),
+                ("C-right", MoveWordNext),
+                ("home", MoveLineStart),
+                ("end", MoveLineEnd),
+                ("up", HistoryPrev),
+                ("down", HistoryNext),
+                ("tab", CompleteNext),
+                ("backtab", CompletePrev),
+            ]),
+        ),
+        (
+            "select",
+            bindings(
+                &[
+                    &motions[..],
+                    &[
+                        ("esc", NormalMode),
+                        ("i", SelectInside),
+                        ("a", SelectAround),
+                        ("d", DeleteSelection),
+                        ("c", ChangeSelection),
+                        ("y", YankSelection),
+                        ("S", SurroundSelection),
+                        ("C-n", AddCursorNextMatch),
+                        ("\"", SelectRegister),
+                    ],
+                ]
+                .concat(),
+            ),
+        ),
+        (
+            "preview",
+            bindings(&[
+                ("y", AcceptChange),
+                ("enter", AcceptChange),
+                ("n", SkipChange),
+                ("a", AcceptAll),
+                ("q", StopChanges),
+                ("esc", CancelChanges),
+            ]),
+        ),
+        (
+            "entity",
+            bindings(&[
+                ("n", EntityName),
+                ("p", EntityParam),
+                ("r", EntityResult),
+                ("b", EntityBody),
+                ("f", EntityFunction),
+            ]),
+        ),
+    ]
+}
+
+impl Keymap {
+    fn from_layers(layers: [(&str, Bindings); 6]) -> Self {
+        let mut tries: HashMap<&str, KeyTrie> = layers
+            .iter()
+            .map(|(name, bindings)| (*name, KeyTrie::new(bindings)))
+            .collect();
+        let mut layer = |name| tries.remove(name).unwrap_or_default();
+        Self {
+            normal: layer("normal"),
+            insert: layer("insert"),
+            command: layer("command"),
+            select: layer("select"),
+            preview: layer("preview"),
+            entity: layer("entity"),
+        }
+    }
+
+    // defaults with the configured bindings on top, returns every problem found on the way
+    pub fn load(settings: &KeySettings) -> (Self, Vec<String>) {
+        let mut layers = default_layers();
+        let mut problems = Vec::new();
+        for (layer, bindings) in layers.iter_mut() {
+            let config = match *layer {
+                "normal" => &settings.normal,
+                "insert" => &settings.insert,
+                "command" => &settings.command,
+                "select" => &settings.select,
+                "preview" => &settings.preview,
+                _ => &settings.entity,
+            };
+            apply_config(layer, bindings, config, &mut problems);
+            if *layer == "entity" {
+                check_single_keys(bindings, &mut problems);
+            }
+        }
+        (Self::from_layers(layers), problems)
+    }
+
+    pub fn bindings(&self, mode: Mode) -> &KeyTrie {
+        match mode {
+            Mode::Normal => &self.normal,
+            Mode::Insert => &self.insert,
+            Mode::Command => &self.command,
+            Mode::Select => &self.select,
+            Mode::Preview => &self.preview,
+        }
+    }
+
+    // the entity layer only holds single keys
+    pub fn entity_command(&self, key: Key) -> Option<Command> {
+        self.entity.get(&[key]).and_then(|node| node.command)
+    }
+
+    pub fn lookup(trie: &KeyTrie, keys: &[Key]) -> KeyMatch {
+        match trie.get(keys) {
+            None => KeyMatch::None,
+            Some(node) if node.children.is_empty() => {
+                node.command.map_or(KeyMatch::None, KeyMatch::Command)
+            }
+            Some(_) => KeyMatch::Pending,
+        }
+    }
+}
+
+fn apply_config(
+    layer: &str,
+    bindings: &mut Bindings,
+    config: &HashMap<String, String>,
+    problems: &mut Vec<String>,
+) {
+    // sorted so reports do not depend on hash order
+    let mut entries: Vec<(&String, &String)> = config.iter().collect();
+    entries.sort();
+    let mut configured: HashMap<Vec<Key>, (&String, Command)> = HashMap::new();
+    let defaults = bindings.clone();
+
+    for (keys_text, command_text) in entries {
+        let keys = match parse_sequence(keys_text) {
+            Ok(keys) => keys,
+            Err(e) => {
+                problems.push(format!("keys.{}: {}", layer, e));
+                continue;
+            }
+        };
+        let Ok(command) = command_text.parse::<Command>() else {
+            problems.push(format!(
+                "keys.{}: unknown command \"{}\" for \"{}\"",
+                layer, command_text, keys_text
+            ));
+            continue;
+        };
+        // "space" and " ", or "S-tab" and "backtab", end up being the same key
+        if let Some((other_text, other)) = configured.get(&keys)
+            && *other != command
+        {
+            problems.push(format!(
+                "keys.{}: \"{}\" ({}) and \"{}\" ({}) are the same key, keeping {}",
+                layer, other_text, other, keys_text, command, other
+            ));
+            continue;
+        }
+        configured.insert(keys.clone(), (keys_text, command));
+        bindings.insert(keys, command);
+    }
+
+    let mut configured: Vec<(Vec<Key>, Command)> = configured
+        .into_iter()
+        .map(|(keys, (_, command))| (keys, command))
+        .filter(|(_, command)| *command != Command::NoOp)
+        .collect();
+    configured.sort_by_key(|(keys, _)| sequence_to_string(keys));
+    for (keys, command) in &configured {
+        // a default that lost its only key, unbinding it with no_op is on purpose
+        if let Some(default) = defaults.get(keys)
+            && default != command
+            && !bindings.values().any(|bound| bound == default)
+        {
+            problems.push(format!(
+                "keys.{}: \"{}\" ({}) takes the only key of {}",
+                layer,
+                sequence_to_string(keys),
+                command,
+                default
+            ));
+        }
+        // the shorter binding waits for the key timeout before it runs
+        let mut longer: Vec<(&Vec<Key>, &Command)> = bindings
+            .iter()
+            .filter(|(other, bound)| {
+                **bound != Command::NoOp && other.len() > keys.len() && other.starts_with(keys)
+            })
+            .collect();
+        let shorter = (1..keys.len())
+            .filter_map(|len| Some((&keys[..len], bindings.get(&keys[..len])?)))
+            .filter(|(short, bound)| {
+                **bound != Command::NoOp && !configured.iter().any(|(keys, _)| keys == short)
+            });
+        longer.sort_by_key(|(other, _)| sequence_to_string(other));
+        let overlaps = longer
+            .into_iter()
+            .map(|(other, bound)| ((keys.as_slice(), command), (other.as_slice(), bound)))
+            .chain(shorter.map(|(short, bound)| ((short, bound), (keys.as_slice(), command))));
+        for ((short, short_command), (long, long_command)) in overlaps {
+            problems.push(format!(
+                "keys.{}: \"{}\" ({}) is the start of \"{}\" ({}), it only runs after the key timeout",
+                layer,
+                sequence_to_string(short),
+                short_command,
+                sequence_to_string(long),
+                long_command
+            ));
+        }
+    }
+}
+
+fn check_single_keys(bindings: &mut Bindings, problems: &mut Vec<String>) {
+    bindings.retain(|keys, command| {
+        let is_entity = command.to_entity_state().is_some()
+            || matches!(command, Command::EntityFunction | Command::NoOp);
+        if keys.len() != 1 || !is_entity {
+            problems.push(format!(
+                "keys.entity: \"{}\" must be a single key bound to an entity",
+                sequence_to_string(keys)
+            ));
+            return false;
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEventKind;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key { code, modifiers }
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(
+            Key::parse("h"),
+            Ok(key(KeyCode::Char('h'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            Key::parse("G"),
+            Ok(key(KeyCode::Char('G'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            Key::parse("-"),
+            Ok(key(KeyCode::Char('-'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            Key::parse("C-r"),
+            Ok(key(KeyCode::Char('r'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            Key::parse("C-A-x"),
+            Ok(key(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ))
+        );
+        assert_eq!(Key::parse("Esc"), Ok(key(KeyCode::Esc, KeyModifiers::NONE)));
+        assert_eq!(
+            Key::parse("S-left"),
+            Ok(key(KeyCode::Left, KeyModifiers::SHIFT))
+        );
+    }
+
+    #[test]
+    fn parse_same_keys() {
+        assert_eq!(Key::parse("space"), Key::parse(" "));
+        assert_eq!(Key::parse("S-tab"), Key::parse("backtab"));
+        assert_eq!(Key::parse("S-a"), Key::parse("a"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Key::parse("").is_err());
+        assert!(Key::parse("X-a").is_err());
+        assert!(Key::parse("nope").is_err());
+        assert!(parse_sequence("  ").is_err());
+    }
+
+    #[test]
+    fn parsed_keys_match_key_events() {
+        let event = |code, modifiers| {
+            Key::from(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                state: crossterm::event::KeyEventState::NONE,
+            })
+        };
+        assert_eq!(
+            Key::parse("G"),
+            Ok(event(KeyCode::Char('G'), KeyModifiers::SHIFT))
+        );
+        assert_eq!(
+            Key::parse("S-tab"),
+            Ok(event(KeyCode::BackTab, KeyModifiers::SHIFT))
+        );
+        assert_eq!(
+            Key::parse("C-r"),
+            Ok(event(KeyCode::Char('r'), KeyModifiers::CONTROL))
+        );
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        let keys = parse_sequence("g  g C-r space S-tab").unwrap();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(sequence_to_string(&keys), "g g C-r space backtab");
+        assert_eq!(parse_sequence(&sequence_to_string(&keys)), Ok(keys));
+    }
+
+    // problems found when `config` is put on top of the default normal layer
+    fn config_problems(config: &[(&str, &str)]) -> (Bindings, Vec<String>) {
+        let [(_, mut bindings), ..] = default_layers();
+        let config = config
+            .iter()
+            .map(|(keys, command)| (keys.to_string(), command.to_string()))
+            .collect();
+        let mut problems = Vec::new();
+        apply_config("normal", &mut bindings, &config, &mut problems);
+        (bindings, problems)
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let (bindings, problems) = config_problems(&[("x", "undo"), ("C-z", "undo")]);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(bindings[&parse_sequence("x").unwrap()], Command::Undo);
+        assert_eq!(bindings[&parse_sequence("u").unwrap()], Command::Undo);
+    }
+
+    #[test]
+    fn config_reports_bad_entries() {
+        let (_, problems) = config_problems(&[("X-a", "undo"), ("x", "nope")]);
+        assert_eq!(problems.len(), 2);
+        let (bindings, problems) = config_problems(&[("backtab", "undo"), ("S-tab", "redo")]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("same key"), "{:?}", problems);
+        assert_eq!(bindings[&parse_sequence("S-tab").unwrap()], Command::Redo);
+    }
+
+    #[test]
+    fn config_reports_defaults_left_without_a_key() {
+        let (_, problems) = config_problems(&[("u", "redo")]);
+        assert_eq!(
+            problems,
+            ["keys.normal: \"u\" (redo) takes the only key of undo"]
+        );
+        // still reachable, or unbound on purpose
+        let (_, problems) = config_problems(&[("u", "redo"), ("U", "undo")]);
+        assert!(problems.is_empty(), "{:?}", problems);
+        let (_, problems) = config_problems(&[("u", "no_op")]);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn config_reports_prefixes() {
+        let (_, problems) = config_problems(&[("g", "undo")]);
+        assert_eq!(
+            problems,
+            [
+                "keys.normal: \"g\" (undo) is the start of \"g g\" (goto_first_line), it only runs after the key timeout"
+            ]
+        );
+        let (_, problems) = config_problems(&[("u x", "redo")]);
+        assert_eq!(
+            problems,
+            [
+                "keys.normal: \"u\" (undo) is the start of \"u x\" (redo), it only runs after the key timeout"
+            ]
+        );
+        // reported once when both are configured
+        let (_, problems) = config_problems(&[("x", "undo"), ("x y", "redo")]);
+        assert_eq!(problems.len(), 1);
+    }
+}
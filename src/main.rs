@@ -1,16 +1,41 @@
+mod cmdline;
+mod command;
 mod cursor;
 mod editor;
+mod events;
 mod features;
 mod input;
+mod keymap;
+mod motion;
+mod pending;
+mod position;
+mod register;
+mod search;
 mod settings;
+mod substitute;
+use cmdline::PromptHistory;
 use color_eyre::Result;
-use crossterm::{ExecutableCommand, cursor::SetCursorStyle, style};
+use crossterm::{
+    ExecutableCommand,
+    cursor::SetCursorStyle,
+    event::{DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange},
+};
 use editor::Document;
-use input::handle_input;
-use ratatui::DefaultTerminal;
+use events::Events;
+use input::handle_event;
+use keymap::Keymap;
+use ratatui::{DefaultTerminal, layout::Rect};
+use register::Registers;
+use settings::Settings;
+use std::path::Path;
+use theme::Theme;
 mod files;
 mod highlight;
+mod history;
 mod snippets;
+mod textobject;
+mod theme;
+mod transaction;
 use snippets::function;
 
 // f -> p -> 1 number -> value // change function argument name
@@ -19,27 +44,76 @@ use snippets::function;
 //
 // f -> a -> number (none if only one) / type -> value
 
-fn run(mut terminal: DefaultTerminal) -> Result<()> {
-    // let file = read_file("Cargo.toml").unwrap();
+fn run(mut terminal: DefaultTerminal, document: &mut Document) -> Result<()> {
     let fn_snippet = &mut function::Snippet::default();
-    let document = &mut Document::new("");
-    terminal.backend_mut().execute(SetCursorStyle::SteadyBlock);
+    terminal
+        .backend_mut()
+        .execute(SetCursorStyle::SteadyBlock)?
+        .execute(EnableBracketedPaste)?
+        .execute(EnableFocusChange)?;
 
-    loop {
-        terminal.draw(|f| document.ui(f, fn_snippet))?;
-        // pass viewport height to input handler so scrolling/clamping can be computed
-        let viewport_height = Some((terminal.size()?.height as usize).saturating_sub(0)); // editor height used in ui
-        if handle_input(document, fn_snippet, &mut terminal, viewport_height)? {
-            break;
+    let events = Events::new();
+    events.spawn_terminal();
+    events.spawn_watcher();
+    let mut watched = document.path.clone();
+    events.watch(watched.clone());
+    // scrolling needs the size of the text area, updated again on resize
+    let size = terminal.size()?;
+    document.screen = Rect::new(0, 0, size.width, size.height);
+    document.scroll_to_cursor();
+
+    let result = loop {
+        if document.redraw {
+            terminal.draw(|f| document.ui(f, fn_snippet))?;
+            document.redraw = false;
         }
-    }
-    Ok(())
+        let timeout = document.settings.editor.key_timeout();
+        let event = events.next(document.pending.deadline(timeout))?;
+        let quit = handle_event(document, fn_snippet, &mut terminal, event);
+        // :w <path> and :open move the buffer to another file
+        if document.path != watched {
+            watched = document.path.clone();
+            events.watch(watched.clone());
+        }
+        match quit {
+            Ok(false) => {}
+            Ok(true) => break Ok(()),
+            Err(e) => break Err(e.into()),
+        }
+    };
+    terminal
+        .backend_mut()
+        .execute(DisableBracketedPaste)?
+        .execute(DisableFocusChange)?;
+    result
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    // open before entering the tui so read errors are printed normally
+    let mut document = match std::env::args().nth(1) {
+        Some(path) => Document::open(Path::new(&path))?,
+        None => Document::new(""),
+    };
+    // a broken config should not keep the file from opening, every problem
+    // with it is shown at once
+    let (settings, mut problems) = Settings::load();
+    document.settings = settings;
+    document.registers = Registers::new(document.settings.editor.clipboard);
+    document.command_history = PromptHistory::load();
+    let theme = &document.settings.theme;
+    match Theme::load(&theme.name, theme.colors) {
+        Ok(theme) => document.theme = theme,
+        Err(e) => problems.push(e.to_string()),
+    }
+    let (keymap, keymap_problems) = Keymap::load(&document.settings.keys);
+    document.keymap = keymap;
+    problems.extend(keymap_problems);
+    if !problems.is_empty() {
+        document.message = Some(problems.join("; "));
+    }
     let terminal = ratatui::init();
-    let result = run(terminal);
+    let result = run(terminal, &mut document);
     ratatui::restore();
     result
 }
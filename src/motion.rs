@@ -0,0 +1,310 @@
+use ropey::Rope;
+
+use crate::position::{line_len, next_grapheme, prev_grapheme};
+
+// where a cursor goes, vertical motions are handled by the cursor because they
+// need the column it wants to stay in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordNext, // words are runs of letters/digits/_ or runs of other symbols
+    WordPrev,
+    WordEnd,
+    BigWordNext, // WORDs are runs of anything but whitespace
+    BigWordPrev,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+    ParagraphNext,
+    ParagraphPrev,
+    FindNext(char), // on the next `char` in the line
+    TillNext(char), // right before it
+    FindPrev(char),
+    TillPrev(char),
+    GotoLine(usize), // 1 based, like a count in front of gg or G
+}
+
+impl Motion {
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Motion::Up | Motion::Down)
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Symbol,
+}
+
+fn class(c: char, big: bool) -> CharClass {
+    match c {
+        c if c.is_whitespace() => CharClass::Space,
+        _ if big => CharClass::Word,
+        c if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Symbol,
+    }
+}
+
+// the horizontal motions, up and down return idx unchanged
+pub fn apply(text: &Rope, idx: usize, motion: Motion) -> usize {
+    let idx = idx.min(text.len_chars());
+    let row = text.char_to_line(idx);
+    let line_start = text.line_to_char(row);
+    match motion {
+        Motion::Left => prev_grapheme(text, idx),
+        Motion::Right => next_grapheme(text, idx),
+        Motion::Up | Motion::Down => idx,
+        Motion::WordNext => word_next(text, idx, false),
+        Motion::WordPrev => word_prev(text, idx, false),
+        Motion::WordEnd => word_end(text, idx, false),
+        Motion::BigWordNext => word_next(text, idx, true),
+        Motion::BigWordPrev => word_prev(text, idx, true),
+        Motion::BigWordEnd => word_end(text, idx, true),
+        Motion::LineStart => line_start,
+        Motion::FirstNonBlank => first_non_blank(text, row),
+        // after the last char, so insert mode appends to the line
+        Motion::LineEnd => line_start + line_len(text, row),
+        Motion::DocumentStart => 0,
+        Motion::DocumentEnd => first_non_blank(text, last_row(text)),
+        Motion::GotoLine(line) => first_non_blank(text, line.clamp(1, last_row(text) + 1) - 1),
+        Motion::ParagraphNext => paragraph(text, row, true),
+        Motion::ParagraphPrev => paragraph(text, row, false),
+        Motion::FindNext(target) => find_next(text, idx, target, false),
+        Motion::TillNext(target) => find_next(text, idx, target, true),
+        Motion::FindPrev(target) => find_prev(text, idx, target, false),
+        Motion::TillPrev(target) => find_prev(text, idx, target, true),
+    }
+}
+
+// a trailing line break does not start another line worth jumping to
+fn last_row(text: &Rope) -> usize {
+    let rows = text.len_lines();
+    match rows > 1 && text.line(rows - 1).len_chars() == 0 {
+        true => rows - 2,
+        false => rows - 1,
+    }
+}
+
+fn first_non_blank(text: &Rope, row: usize) -> usize {
+    let line_start = text.line_to_char(row);
+    let blanks = text
+        .line(row)
+        .chars()
+        .take(line_len(text, row))
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count();
+    line_start + blanks
+}
+
+fn word_next(text: &Rope, idx: usize, big: bool) -> usize {
+    let mut chars = text.chars_at(idx).peekable();
+    let mut idx = idx;
+    let Some(first) = chars.next() else {
+        return idx;
+    };
+    idx += 1;
+    let start = class(first, big);
+    // rest of the current word, then the whitespace after it
+    while let Some(c) = chars.peek() {
+        let current = class(*c, big);
+        if current == CharClass::Space || current != start {
+            break;
+        }
+        chars.next();
+        idx += 1;
+    }
+    while chars.next_if(|c| c.is_whitespace()).is_some() {
+        idx += 1;
+    }
+    idx
+}
+
+fn word_end(text: &Rope, idx: usize, big: bool) -> usize {
+    let mut chars = text.chars_at(idx).peekable();
+    let mut idx = idx;
+    // always leaves the current char, so repeating walks to the next word
+    if chars.next().is_none() {
+        return idx;
+    }
+    idx += 1;
+    while chars.next_if(|c| c.is_whitespace()).is_some() {
+        idx += 1;
+    }
+    let Some(first) = chars.next() else {
+        return text.len_chars();
+    };
+    let start = class(first, big);
+    while chars.next_if(|c| class(*c, big) == start).is_some() {
+        idx += 1;
+    }
+    idx
+}
+
+fn word_prev(text: &Rope, idx: usize, big: bool) -> usize {
+    let mut chars = text.chars_at(idx).reversed().peekable();
+    let mut idx = idx;
+    while chars.next_if(|c| c.is_whitespace()).is_some() {
+        idx -= 1;
+    }
+    let Some(first) = chars.next() else {
+        return idx;
+    };
+    idx -= 1;
+    let start = class(first, big);
+    while chars.next_if(|c| class(*c, big) == start).is_some() {
+        idx -= 1;
+    }
+    idx
+}
+
+// the next/previous empty line, or the document edge
+fn paragraph(text: &Rope, row: usize, forward: bool) -> usize {
+    let is_blank = |row: usize| text.line(row).chars().all(char::is_whitespace);
+    let last = last_row(text);
+    let mut row = row;
+    // leave the blank lines the cursor is in first
+    let step = |row: usize| match forward {
+        true => (row < last).then(|| row + 1),
+        false => row.checked_sub(1),
+    };
+    while is_blank(row) {
+        match step(row) {
+            Some(next) => row = next,
+            None => break,
+        }
+    }
+    while let Some(next) = step(row) {
+        row = next;
+        if is_blank(row) {
+            return text.line_to_char(row);
+        }
+    }
+    match forward {
+        true => text.line_to_char(row) + line_len(text, row),
+        false => 0,
+    }
+}
+
+fn find_next(text: &Rope, idx: usize, target: char, till: bool) -> usize {
+    let row = text.char_to_line(idx);
+    let line_end = text.line_to_char(row) + line_len(text, row);
+    // till starts one further so repeating it does not get stuck in front of target
+    let skip = if till { 2 } else { 1 };
+    let start = (idx + skip).min(line_end);
+    text.chars_at(start)
+        .take(line_end - start)
+        .position(|c| c == target)
+        .map_or(idx, |offset| start + offset - usize::from(till))
+}
+
+fn find_prev(text: &Rope, idx: usize, target: char, till: bool) -> usize {
+    let line_start = text.line_to_char(text.char_to_line(idx));
+    let skip = if till { 1 } else { 0 };
+    let start = idx.saturating_sub(skip).max(line_start);
+    text.chars_at(start)
+        .reversed()
+        .take(start - line_start)
+        .position(|c| c == target)
+        .map_or(idx, |offset| start - offset - 1 + usize::from(till))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every stop of a motion repeated from the start of the text
+    fn stops(text: &str, motion: Motion) -> Vec<usize> {
+        let text = Rope::from(text);
+        let mut idx = 0;
+        let mut stops = Vec::new();
+        loop {
+            let next = apply(&text, idx, motion);
+            if next == idx {
+                return stops;
+            }
+            stops.push(next);
+            idx = next;
+        }
+    }
+
+    fn back_stops(text: &str, motion: Motion) -> Vec<usize> {
+        let rope = Rope::from(text);
+        let mut idx = rope.len_chars();
+        let mut stops = Vec::new();
+        loop {
+            let next = apply(&rope, idx, motion);
+            if next == idx {
+                return stops;
+            }
+            stops.push(next);
+            idx = next;
+        }
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo.bar  baz_1 (x)";
+        assert_eq!(stops(text, Motion::WordNext), [3, 4, 9, 15, 16, 17, 18]);
+        assert_eq!(stops(text, Motion::BigWordNext), [9, 15, 18]);
+        assert_eq!(stops(text, Motion::WordEnd), [2, 3, 6, 13, 15, 16, 17, 18]);
+        assert_eq!(back_stops(text, Motion::WordPrev), [17, 16, 15, 9, 4, 3, 0]);
+        assert_eq!(back_stops(text, Motion::BigWordPrev), [15, 9, 0]);
+    }
+
+    #[test]
+    fn words_cross_lines() {
+        assert_eq!(stops("ab\n  cd", Motion::WordNext), [5, 7]);
+        assert_eq!(back_stops("ab\n  cd", Motion::WordPrev), [5, 0]);
+    }
+
+    #[test]
+    fn lines() {
+        let text = Rope::from("ab\n  cd ef\r\nx");
+        assert_eq!(apply(&text, 7, Motion::LineStart), 3);
+        assert_eq!(apply(&text, 7, Motion::FirstNonBlank), 5);
+        assert_eq!(apply(&text, 4, Motion::LineEnd), 10);
+        assert_eq!(apply(&text, 1, Motion::Right), 2);
+        assert_eq!(apply(&text, 3, Motion::Left), 2);
+    }
+
+    #[test]
+    fn document() {
+        let text = Rope::from("a\n  b\n\tc\n");
+        assert_eq!(apply(&text, 4, Motion::DocumentStart), 0);
+        // the empty line after the trailing break is not a line to go to
+        assert_eq!(apply(&text, 0, Motion::DocumentEnd), 7);
+        assert_eq!(apply(&text, 0, Motion::GotoLine(2)), 4);
+        assert_eq!(apply(&text, 0, Motion::GotoLine(0)), 0);
+        assert_eq!(apply(&text, 0, Motion::GotoLine(99)), 7);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\n\nc\n\nd";
+        assert_eq!(stops(text, Motion::ParagraphNext), [4, 8, 10]);
+        assert_eq!(back_stops(text, Motion::ParagraphPrev), [8, 5, 0]);
+    }
+
+    #[test]
+    fn finds_stay_in_the_line() {
+        let text = Rope::from("a,b,c\nd,e");
+        assert_eq!(apply(&text, 0, Motion::FindNext(',')), 1);
+        assert_eq!(apply(&text, 1, Motion::FindNext(',')), 3);
+        assert_eq!(apply(&text, 3, Motion::FindNext(',')), 3);
+        assert_eq!(apply(&text, 0, Motion::TillNext('c')), 3);
+        // till looks past the char right next to the cursor, so repeating it moves on
+        assert_eq!(apply(&text, 0, Motion::TillNext(',')), 2);
+        assert_eq!(apply(&text, 2, Motion::TillNext(',')), 2);
+        assert_eq!(apply(&text, 4, Motion::FindPrev(',')), 3);
+        assert_eq!(apply(&text, 3, Motion::FindPrev(',')), 1);
+        assert_eq!(apply(&text, 4, Motion::TillPrev('a')), 1);
+        assert_eq!(apply(&text, 7, Motion::FindPrev(',')), 7);
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use crossterm::event::KeyCode;
+
+use crate::{keymap::Key, motion::Motion, snippets::snippet::SnippetState};
+
+// commands that take the next typed key as their argument
+#[derive(Clone, Copy)]
+pub enum PendingChar {
+    Motion(fn(char) -> Motion), // f/t/F/T
+    Object { around: bool },    // i/a in select mode, a delimiter or an entity key
+    Surround,
+    Register,             // " before a yank, delete or paste
+    InsertRegister,       // C-r in insert mode pastes right away
+    Action(SnippetState), // an action waits for the entity key it applies to
+}
+
+/// A command that is still being typed. `3"ad n` arrives one key at a time:
+/// the count, the register, the keys of a binding looked up in the keymap trie
+/// and at last the key the command waits for. Esc drops all of it, and the
+/// keys typed so far are shown in the status area meanwhile.
+#[derive(Default)]
+pub struct Pending {
+    pub count: Option<usize>,
+    pub register: Option<char>,
+    pub sequence: Vec<Key>, // start of a multi key binding
+    pub awaiting: Option<PendingChar>,
+    typed: Vec<Key>,        // everything above in typing order, for the status area
+    since: Option<Instant>, // last key of `sequence`, it times out from there
+}
+
+impl Pending {
+    pub fn is_empty(&self) -> bool {
+        self.typed.is_empty()
+    }
+
+    pub fn record(&mut self, key: Key) {
+        self.typed.push(key);
+    }
+
+    pub fn push_digit(&mut self, digit: u32) {
+        let count = self.count.unwrap_or(0);
+        self.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+    }
+
+    pub fn push_key(&mut self, key: Key) {
+        self.sequence.push(key);
+        self.since = Some(Instant::now());
+    }
+
+    // the binding is complete, the command waits for one more key
+    pub fn wait(&mut self, awaiting: PendingChar) {
+        self.sequence.clear();
+        self.since = None;
+        self.awaiting = Some(awaiting);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    // when the start of a multi key binding stops waiting for the rest
+    pub fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.since.map(|since| since + timeout)
+    }
+
+    // `3"ad`, keys without a char of their own in angle brackets
+    pub fn feedback(&self) -> String {
+        self.typed
+            .iter()
+            .map(|key| match (key.code, key.modifiers.is_empty()) {
+                (KeyCode::Char(' '), _) => "<space>".to_string(),
+                (KeyCode::Char(c), true) => c.to_string(),
+                _ => format!("<{}>", key),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,179 @@
+use ropey::Rope;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Three ways of pointing into the text, kept apart on purpose:
+// - char index: offset into the rope, stored by selections, transactions and
+//   entity ranges. `Pos.x` is the same thing counted from the line start
+// - grapheme: what reads as one character ("e" + accent, flags, "\r\n"),
+//   cursors move and delete by whole graphemes
+// - display column: terminal cells from the line start, tabs expand to the next
+//   tab stop and wide (CJK, emoji) graphemes take two cells
+
+// chars of a line without its line break
+pub fn line_len(text: &Rope, row: usize) -> usize {
+    let line = text.line(row);
+    let len = line.len_chars();
+    match len > 0 && line.char(len - 1) == '\n' {
+        true if len > 1 && line.char(len - 2) == '\r' => len - 2,
+        true => len - 1,
+        false => len,
+    }
+}
+
+// cells taken by a grapheme that starts at display column `col`
+pub fn grapheme_width(grapheme: &str, col: usize, tab_width: usize) -> usize {
+    match grapheme {
+        "\t" => tab_width.max(1) - col % tab_width.max(1),
+        _ => grapheme.width(),
+    }
+}
+
+// graphemes of a line with the char offset each one starts at
+fn graphemes(text: &Rope, row: usize) -> impl Iterator<Item = (usize, String)> {
+    let line = text.line(row).to_string();
+    let mut offset = 0;
+    line.graphemes(true)
+        .map(|grapheme| {
+            let start = offset;
+            offset += grapheme.chars().count();
+            (start, grapheme.to_string())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+pub fn next_grapheme(text: &Rope, idx: usize) -> usize {
+    if idx >= text.len_chars() {
+        return text.len_chars();
+    }
+    let row = text.char_to_line(idx);
+    let line_start = text.line_to_char(row);
+    graphemes(text, row)
+        .map(|(start, grapheme)| line_start + start + grapheme.chars().count())
+        .find(|end| *end > idx)
+        .unwrap_or(idx + 1)
+}
+
+pub fn prev_grapheme(text: &Rope, idx: usize) -> usize {
+    if idx == 0 {
+        return 0;
+    }
+    // the grapheme before a line start is the line break of the previous line
+    let row = text.char_to_line(idx - 1);
+    let line_start = text.line_to_char(row);
+    graphemes(text, row)
+        .map(|(start, _)| line_start + start)
+        .take_while(|start| *start < idx)
+        .last()
+        .unwrap_or(idx - 1)
+}
+
+// display column of the char `col` chars into `row`
+pub fn display_col(text: &Rope, row: usize, col: usize, tab_width: usize) -> usize {
+    let mut display = 0;
+    for (start, grapheme) in graphemes(text, row) {
+        if start >= col {
+            break;
+        }
+        display += grapheme_width(&grapheme, display, tab_width);
+    }
+    display
+}
+
+// char offset in `row` of the grapheme covering display column `display`,
+// the end of the line when the line is shorter
+pub fn char_col(text: &Rope, row: usize, display: usize, tab_width: usize) -> usize {
+    let len = line_len(text, row);
+    let mut current = 0;
+    for (start, grapheme) in graphemes(text, row) {
+        if start >= len {
+            break;
+        }
+        current += grapheme_width(&grapheme, current, tab_width);
+        if current > display {
+            return start;
+        }
+    }
+    len
+}
+
+// tabs replaced by the spaces they take when the text starts at column `col`,
+// returns the column after the text
+pub fn expand_tabs(text: &str, col: usize, tab_width: usize) -> (String, usize) {
+    let mut expanded = String::with_capacity(text.len());
+    let mut col = col;
+    for grapheme in text.graphemes(true) {
+        let width = grapheme_width(grapheme, col, tab_width);
+        match grapheme {
+            "\t" => expanded.extend(std::iter::repeat_n(' ', width)),
+            _ => expanded.push_str(grapheme),
+        }
+        col += width;
+    }
+    (expanded, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_len_leaves_out_line_breaks() {
+        let text = Rope::from("ab\ncd\r\n\nef");
+        let lens: Vec<usize> = (0..text.len_lines())
+            .map(|row| line_len(&text, row))
+            .collect();
+        assert_eq!(lens, [2, 2, 0, 2]);
+    }
+
+    #[test]
+    fn graphemes_move_as_one() {
+        // e + combining accent, a flag made of two chars, then a crlf
+        let text = Rope::from("ae\u{301}🇩🇪\r\nb");
+        let mut idx = 0;
+        let mut stops = vec![idx];
+        while idx < text.len_chars() {
+            idx = next_grapheme(&text, idx);
+            stops.push(idx);
+        }
+        assert_eq!(stops, [0, 1, 3, 5, 7, 8]);
+        let mut back = vec![idx];
+        while idx > 0 {
+            idx = prev_grapheme(&text, idx);
+            back.push(idx);
+        }
+        stops.reverse();
+        assert_eq!(back, stops);
+        // from inside a grapheme to its edges
+        assert_eq!(next_grapheme(&text, 2), 3);
+        assert_eq!(prev_grapheme(&text, 4), 3);
+    }
+
+    #[test]
+    fn display_columns() {
+        let text = Rope::from("\tb漢e\u{301}x");
+        let cols: Vec<usize> = (0..=6).map(|col| display_col(&text, 0, col, 4)).collect();
+        // the accent shares the cell of its e
+        assert_eq!(cols, [0, 4, 5, 7, 8, 8, 9]);
+        assert_eq!(display_col(&Rope::from("ab\tc"), 0, 3, 4), 4);
+    }
+
+    #[test]
+    fn char_columns() {
+        let text = Rope::from("\tb漢e\u{301}x\n");
+        let chars: Vec<usize> = (0..=10)
+            .map(|display| char_col(&text, 0, display, 4))
+            .collect();
+        // cells inside a tab or a wide char land on its start, past the end on the end
+        assert_eq!(chars, [0, 0, 0, 0, 1, 2, 2, 3, 5, 6, 6]);
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_stop() {
+        assert_eq!(expand_tabs("a\tb", 0, 4), ("a   b".to_string(), 5));
+        assert_eq!(expand_tabs("\t", 2, 4), ("  ".to_string(), 4));
+        assert_eq!(expand_tabs("漢\t", 0, 4), ("漢  ".to_string(), 4));
+        assert_eq!(grapheme_width("\t", 5, 0), 1);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::snippets::function::FunctionValues;
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+// text of a register, one value per cursor it was taken from
+#[derive(Clone, Default)]
+pub struct Yank {
+    pub values: Vec<String>,
+    pub linewise: bool, // whole lines, pasted above or below instead of inside a line
+    pub functions: Vec<FunctionValues>, // yanked snippet entities, pasted as snippets again
+}
+
+impl Yank {
+    pub fn joined(&self) -> String {
+        // whole lines already end in their line break
+        match self.linewise {
+            true => self.values.concat(),
+            false => self.values.join("\n"),
+        }
+    }
+}
+
+// programs that reach the system clipboard, looked up once at startup
+#[derive(Clone, Copy, PartialEq)]
+enum Provider {
+    Wayland,
+    Xclip,
+    None,
+}
+
+impl Provider {
+    fn detect() -> Self {
+        let is_set = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty());
+        if is_set("WAYLAND_DISPLAY") && installed("wl-copy") && installed("wl-paste") {
+            Provider::Wayland
+        } else if is_set("DISPLAY") && installed("xclip") {
+            Provider::Xclip
+        } else {
+            Provider::None
+        }
+    }
+
+    fn copy(self, text: &str) -> io::Result<()> {
+        let mut command = match self {
+            Provider::Wayland => Command::new("wl-copy"),
+            Provider::Xclip => {
+                let mut command = Command::new("xclip");
+                command.args(["-selection", "clipboard"]);
+                command
+            }
+            Provider::None => return Err(io::Error::other("no clipboard provider")),
+        };
+        // the terminal belongs to the editor, nothing may print into it
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        child.wait()?;
+        Ok(())
+    }
+
+    fn paste(self) -> io::Result<String> {
+        let mut command = match self {
+            Provider::Wayland => {
+                let mut command = Command::new("wl-paste");
+                command.arg("--no-newline");
+                command
+            }
+            Provider::Xclip => {
+                let mut command = Command::new("xclip");
+                command.args(["-o", "-selection", "clipboard"]);
+                command
+            }
+            Provider::None => return Err(io::Error::other("no clipboard provider")),
+        };
+        let output = command
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            false => Err(io::Error::other("clipboard is empty")),
+        }
+    }
+}
+
+fn installed(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+pub const UNNAMED: char = '"';
+
+// the ones the document fills in itself: file name and last search
+pub fn is_read_only(name: char) -> bool {
+    matches!(name, '%' | '/')
+}
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphabetic() || matches!(name, '"' | '0' | '-' | '+' | '*') || is_read_only(name)
+}
+
+/// Vim style registers: `"` gets everything, `0` the last yank, `-` the last
+/// delete, `a`-`z` only what is put there explicitly (`A`-`Z` appends). `+` and
+/// `*` are the system clipboard when a provider is installed, and plain
+/// registers otherwise.
+pub struct Registers {
+    values: HashMap<char, Yank>,
+    provider: Provider,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+            provider: Provider::None,
+        }
+    }
+}
+
+impl Registers {
+    pub fn new(system_clipboard: bool) -> Self {
+        Self {
+            values: HashMap::new(),
+            provider: match system_clipboard {
+                true => Provider::detect(),
+                false => Provider::None,
+            },
+        }
+    }
+
+    pub fn get(&self, name: char) -> Option<Yank> {
+        if matches!(name, '+' | '*')
+            && let Ok(text) = self.provider.paste()
+        {
+            // lines copied elsewhere end in a line break
+            let linewise = text.ends_with('\n');
+            return Some(Yank {
+                values: vec![text],
+                linewise,
+                functions: Vec::new(),
+            });
+        }
+        self.values.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    // `deleted` text goes to `-` instead of `0` when no register was named
+    pub fn store(&mut self, name: char, yank: Yank, deleted: bool) -> io::Result<()> {
+        self.values.insert(UNNAMED, yank.clone());
+        match name {
+            UNNAMED if deleted => {
+                self.values.insert('-', yank);
+            }
+            UNNAMED => {
+                self.values.insert('0', yank);
+            }
+            'A'..='Z' => {
+                let current = self.values.entry(name.to_ascii_lowercase()).or_default();
+                current.values.extend(yank.values);
+                current.linewise |= yank.linewise;
+                current.functions.extend(yank.functions);
+            }
+            '+' | '*' => {
+                let text = yank.joined();
+                // kept here too, so the register works without a provider
+                self.values.insert(name, yank);
+                if self.provider != Provider::None {
+                    return self.provider.copy(&text);
+                }
+            }
+            name => {
+                self.values.insert(name, yank);
+            }
+        }
+        Ok(())
+    }
+}
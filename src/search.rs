@@ -0,0 +1,256 @@
+use std::{borrow::Cow, ops::Range};
+
+use regex::{Regex, RegexBuilder};
+use ropey::{Rope, RopeSlice};
+
+use crate::{
+    cmdline::PromptHistory,
+    editor::{Document, Mode, Prompt},
+};
+
+/// `/` and `?` search. The pattern is compiled on every key while it is typed,
+/// matches are looked for line by line in the rope, so a match never spans a
+/// line break.
+#[derive(Default)]
+pub struct Search {
+    pub regex: Option<Regex>, // highlighted and used by n/N
+    pub highlight: bool,      // :noh hides the matches until the next search
+    pub backward: bool,       // direction of the last search, n follows it
+    pub history: PromptHistory,
+    origin: usize, // primary cursor when the prompt opened
+}
+
+// smart case: ignores case until the pattern has an upper case letter
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!has_upper_case(pattern))
+        .build()
+}
+
+// letters of escapes like \W or \p{Lu} are classes, not text to match
+fn has_upper_case(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some('p' | 'P') = chars.next()
+                    && chars.clone().next() == Some('{')
+                {
+                    chars.find(|c| *c == '}');
+                }
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// a line without its line break, so $ matches at its end and \s never eats the break
+pub fn line_content(text: &Rope, row: usize) -> RopeSlice<'_> {
+    let line = text.line(row);
+    let mut len = line.len_chars();
+    for ending in ['\n', '\r'] {
+        if len > 0 && line.char(len - 1) == ending {
+            len -= 1;
+        }
+    }
+    line.slice(..len)
+}
+
+// char ranges of the matches in a line
+fn line_matches(regex: &Regex, text: &Rope, row: usize) -> Vec<Range<usize>> {
+    let line = line_content(text, row);
+    let line_start = text.line_to_char(row);
+    let content: Cow<str> = line.into();
+    regex
+        .find_iter(&content)
+        .map(|found| {
+            line_start + line.byte_to_char(found.start())
+                ..line_start + line.byte_to_char(found.end())
+        })
+        .collect()
+}
+
+// every match in the given rows, for drawing
+pub fn matches_in(regex: &Regex, text: &Rope, rows: Range<usize>) -> Vec<Range<usize>> {
+    rows.flat_map(|row| line_matches(regex, text, row))
+        .collect()
+}
+
+// start of the first match after idx, or before it when `backward`, wrapping
+// around the document. The bool is true when it wrapped
+pub fn find(regex: &Regex, text: &Rope, idx: usize, backward: bool) -> Option<(usize, bool)> {
+    let rows = text.len_lines();
+    let row = text.char_to_line(idx.min(text.len_chars()));
+    // the cursor row comes up twice, once for each side of the cursor
+    let order: Vec<usize> = match backward {
+        false => (row..rows).chain(0..=row).collect(),
+        true => (0..=row).rev().chain((row..rows).rev()).collect(),
+    };
+    for (step, current) in order.into_iter().enumerate() {
+        let wrapped = match backward {
+            false => step >= rows - row,
+            true => step > row,
+        };
+        let starts: Vec<usize> = line_matches(regex, text, current)
+            .into_iter()
+            .map(|found| found.start)
+            .collect();
+        let found = match backward {
+            false => starts.into_iter().find(|start| wrapped || *start > idx),
+            true => starts.into_iter().rfind(|start| wrapped || *start < idx),
+        };
+        if let Some(start) = found {
+            return Some((start, wrapped && start != idx));
+        }
+    }
+    None
+}
+
+// opens the prompt, the cursor comes back here when it is cancelled
+pub fn start(doc: &mut Document, backward: bool) {
+    doc.search.backward = backward;
+    doc.search.origin = doc.cursor_get_idx();
+    doc.search.history.reset();
+    doc.command_line.clear();
+    doc.prompt = Prompt::Search;
+    doc.mode = Mode::Command;
+}
+
+// called after every change of the prompt, moves to the first match as it is typed
+pub fn update(doc: &mut Document) {
+    let origin = doc.search.origin;
+    doc.search.highlight = true;
+    doc.search.regex = match doc.command_line.is_empty() {
+        true => None,
+        // half typed patterns are often invalid, they just do not match yet
+        false => compile(doc.command_line.as_str()).ok(),
+    };
+    let found = doc
+        .search
+        .regex
+        .as_ref()
+        .and_then(|regex| find(regex, &doc.editor.text, origin, doc.search.backward));
+    let idx = found.map_or(origin, |(start, _)| start);
+    doc.cursor.set_idx(&doc.editor.text, idx);
+}
+
+// enter: an empty prompt searches for the last pattern again
+pub fn confirm(doc: &mut Document) {
+    let mut pattern = doc.command_line.take();
+    if pattern.is_empty() {
+        let Some(last) = doc.search.history.entries.last() else {
+            return;
+        };
+        pattern = last.clone();
+    }
+    let regex = match compile(&pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            doc.message = Some(format!("invalid pattern: {}", e));
+            return cancel(doc);
+        }
+    };
+    doc.search.history.push(pattern.clone());
+    doc.last_search = Some(pattern);
+    let origin = doc.search.origin;
+    match find(&regex, &doc.editor.text, origin, doc.search.backward) {
+        Some((start, wrapped)) => {
+            doc.cursor.set_idx(&doc.editor.text, start);
+            if wrapped {
+                doc.message = Some("search wrapped".to_string());
+            }
+        }
+        None => {
+            doc.cursor.set_idx(&doc.editor.text, origin);
+            doc.message = Some(format!("pattern not found: {}", regex.as_str()));
+        }
+    }
+    doc.search.regex = Some(regex);
+    doc.search.highlight = true;
+}
+
+// esc: back to where the search started, highlighting the previous search again
+pub fn cancel(doc: &mut Document) {
+    let origin = doc.search.origin;
+    doc.cursor.set_idx(&doc.editor.text, origin);
+    doc.search.regex = doc
+        .last_search
+        .as_ref()
+        .and_then(|pattern| compile(pattern).ok());
+}
+
+// n and N, `reverse` goes against the direction of the last search
+pub fn jump(doc: &mut Document, reverse: bool, count: usize) {
+    let regex = doc.search.regex.clone().or_else(|| {
+        let pattern = doc.last_search.as_ref()?;
+        compile(pattern).ok()
+    });
+    let Some(regex) = regex else {
+        doc.message = Some("no previous search".to_string());
+        return;
+    };
+    doc.search.highlight = true;
+    let backward = doc.search.backward != reverse;
+    let mut idx = doc.cursor_get_idx();
+    for _ in 0..count {
+        match find(&regex, &doc.editor.text, idx, backward) {
+            Some((start, wrapped)) => {
+                idx = start;
+                if wrapped {
+                    doc.message = Some("search wrapped".to_string());
+                }
+            }
+            None => {
+                doc.message = Some(format!("pattern not found: {}", regex.as_str()));
+                return;
+            }
+        }
+    }
+    doc.cursor.set_idx(&doc.editor.text, idx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(pattern: &str, text: &str) -> Vec<usize> {
+        let regex = compile(pattern).unwrap();
+        let text = Rope::from(text);
+        matches_in(&regex, &text, 0..text.len_lines())
+            .into_iter()
+            .map(|found| found.start)
+            .collect()
+    }
+
+    #[test]
+    fn smart_case() {
+        assert_eq!(starts("foo", "Foo foo"), [0, 4]);
+        assert_eq!(starts("Foo", "Foo foo"), [0]);
+        // escapes are no upper case letters
+        assert_eq!(starts(r"\Sfoo", "xFOO xfoo"), [0, 5]);
+        assert_eq!(starts(r"\p{Greek}a", "πA πa"), [0, 3]);
+        assert_eq!(starts(r"\\A", r"\a \A"), [3]);
+    }
+
+    #[test]
+    fn matches_stay_in_their_line() {
+        assert_eq!(starts("o$", "foo\nbar\r\nzoo"), [2, 11]);
+        assert_eq!(starts("^b", "ab\nba"), [3]);
+        assert_eq!(starts(r"a\s+", "a\nb a \n"), [4]);
+        // matches are counted in chars
+        assert_eq!(starts("b", "éé\nb"), [3]);
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let regex = compile("x").unwrap();
+        let text = Rope::from("x a\nb x\nc");
+        assert_eq!(find(&regex, &text, 0, false), Some((6, false)));
+        assert_eq!(find(&regex, &text, 6, false), Some((0, true)));
+        assert_eq!(find(&regex, &text, 6, true), Some((0, false)));
+        assert_eq!(find(&regex, &text, 0, true), Some((6, true)));
+        assert_eq!(find(&compile("y").unwrap(), &text, 0, false), None);
+    }
+}
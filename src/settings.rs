@@ -1,3 +1,366 @@
-use crossterm::event::KeyCode::{self, Char};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::{Table, Value};
 
-fn setting_load() {}
+/// Read from three optional TOML files merged on top of the built-in defaults,
+/// later layers win key by key:
+///
+/// 1. team file `.elarge/team.toml` in the project root, shared by everyone
+/// 2. user file `~/.config/elarge/config.toml`, personal preferences
+/// 3. project file `.elarge.toml` in the project root, overrides for this checkout
+///
+/// The project root is the closest directory (starting at the working directory)
+/// that holds `.elarge.toml` or `.elarge/`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub editor: EditorSettings,
+    pub highlight: HighlightSettings,
+    pub snippet: SnippetSettings,
+    pub keys: KeySettings,
+    pub theme: ThemeSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EditorSettings {
+    pub undo_group_ms: u64,   // insert mode edits closer than this undo together
+    pub key_timeout_ms: u64,  // wait for the next key of a multi key binding
+    pub scrolloff: usize,     // rows kept visible above and below the cursor
+    pub sidescrolloff: usize, // columns kept visible left and right of the cursor
+    pub tab_width: usize,
+    pub indent_width: usize, // columns of one indentation level
+    pub expand_tab: bool,    // indent with spaces instead of tab characters
+    pub clipboard: bool,     // + and * use xclip/wl-copy when installed
+    pub which_key: bool,     // popup with the keys that can follow a pending command
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HighlightSettings {
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SnippetSettings {
+    pub function: FunctionSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FunctionSettings {
+    pub keyword: String, // written in front of the function name
+    pub result: String,  // separates params from the result type
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub name: String, // file name without .toml in a themes directory
+    pub colors: ColorSupport,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ColorSupport {
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+// key sequence -> command name per mode, on top of the default keymap.
+// `entity` holds the keys read after an action (`d` then `n` deletes the name)
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct KeySettings {
+    pub normal: HashMap<String, String>,
+    pub insert: HashMap<String, String>,
+    pub command: HashMap<String, String>,
+    pub select: HashMap<String, String>,
+    pub preview: HashMap<String, String>,
+    pub entity: HashMap<String, String>,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            undo_group_ms: 1000,
+            key_timeout_ms: 1000,
+            scrolloff: 3,
+            sidescrolloff: 5,
+            tab_width: 4,
+            indent_width: 4,
+            expand_tab: true,
+            clipboard: true,
+            which_key: true,
+        }
+    }
+}
+
+impl Default for HighlightSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            colors: ColorSupport::Auto,
+        }
+    }
+}
+
+impl Default for FunctionSettings {
+    fn default() -> Self {
+        Self {
+            keyword: "fn".to_string(),
+            result: "->".to_string(),
+        }
+    }
+}
+
+impl EditorSettings {
+    pub fn undo_group_timeout(&self) -> Duration {
+        Duration::from_millis(self.undo_group_ms)
+    }
+
+    pub fn key_timeout(&self) -> Duration {
+        Duration::from_millis(self.key_timeout_ms)
+    }
+
+    // one indentation level, for a line that starts at nothing
+    pub fn indent_unit(&self) -> String {
+        match self.expand_tab {
+            true => " ".repeat(self.indent_width.max(1)),
+            false => "\t".to_string(),
+        }
+    }
+}
+
+// what `:set` can change while the editor runs
+pub const OPTIONS: &[&str] = &[
+    "undo_group_ms",
+    "key_timeout_ms",
+    "scrolloff",
+    "sidescrolloff",
+    "tab_width",
+    "indent_width",
+    "expand_tab",
+    "which_key",
+    "highlight",
+];
+
+impl Settings {
+    pub fn get(&self, option: &str) -> Option<String> {
+        let editor = &self.editor;
+        let value = match option {
+            "undo_group_ms" => editor.undo_group_ms.to_string(),
+            "key_timeout_ms" => editor.key_timeout_ms.to_string(),
+            "scrolloff" => editor.scrolloff.to_string(),
+            "sidescrolloff" => editor.sidescrolloff.to_string(),
+            "tab_width" => editor.tab_width.to_string(),
+            "indent_width" => editor.indent_width.to_string(),
+            "expand_tab" => editor.expand_tab.to_string(),
+            "which_key" => editor.which_key.to_string(),
+            "highlight" => self.highlight.enabled.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("{}: {}", option, e);
+        let editor = &mut self.editor;
+        match option {
+            "undo_group_ms" => editor.undo_group_ms = value.parse().map_err(|e| invalid(&e))?,
+            "key_timeout_ms" => editor.key_timeout_ms = value.parse().map_err(|e| invalid(&e))?,
+            "scrolloff" => editor.scrolloff = value.parse().map_err(|e| invalid(&e))?,
+            "sidescrolloff" => editor.sidescrolloff = value.parse().map_err(|e| invalid(&e))?,
+            "tab_width" => match value.parse() {
+                Ok(0) => return Err(invalid(&"must be at least 1")),
+                Ok(width) => editor.tab_width = width,
+                Err(e) => return Err(invalid(&e)),
+            },
+            "indent_width" => match value.parse() {
+                Ok(0) => return Err(invalid(&"must be at least 1")),
+                Ok(width) => editor.indent_width = width,
+                Err(e) => return Err(invalid(&e)),
+            },
+            "expand_tab" => editor.expand_tab = value.parse().map_err(|e| invalid(&e))?,
+            "which_key" => editor.which_key = value.parse().map_err(|e| invalid(&e))?,
+            "highlight" => self.highlight.enabled = value.parse().map_err(|e| invalid(&e))?,
+            _ => return Err(format!("unknown option: {}", option)),
+        }
+        Ok(())
+    }
+
+    // files in order of precedence, lowest first
+    fn layer_paths() -> Vec<PathBuf> {
+        let root = project_root();
+        let mut paths = vec![root.join(".elarge").join("team.toml")];
+        if let Some(config_dir) = config_dir() {
+            paths.push(config_dir.join("config.toml"));
+        }
+        paths.push(root.join(".elarge.toml"));
+        paths
+    }
+
+    pub fn load() -> (Self, Vec<String>) {
+        Self::load_layers(&Self::layer_paths())
+    }
+
+    // a broken layer is left out and reported, the others still apply
+    fn load_layers(paths: &[PathBuf]) -> (Self, Vec<String>) {
+        let mut merged = Table::new();
+        let mut problems = Vec::new();
+        for path in paths {
+            match read_layer(path) {
+                Ok(Some(layer)) => merge(&mut merged, layer),
+                Ok(None) => {}
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+        match Value::Table(merged).try_into() {
+            Ok(settings) => (settings, problems),
+            Err(e) => {
+                problems.push(format!("config: {}", e));
+                (Self::default(), problems)
+            }
+        }
+    }
+}
+
+// user level config directory, respects XDG_CONFIG_HOME
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("elarge"))
+}
+
+// history and other state kept between sessions, respects XDG_DATA_HOME
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(base.join("elarge"))
+}
+
+pub fn project_root() -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    cwd.ancestors()
+        .find(|dir| dir.join(".elarge.toml").is_file() || dir.join(".elarge").is_dir())
+        .unwrap_or(&cwd)
+        .to_path_buf()
+}
+
+// a missing file is an empty layer
+fn read_layer(path: &Path) -> io::Result<Option<Table>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let invalid = |e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let layer = text.parse::<Table>().map_err(|e| invalid(&e.message()))?;
+    // values of the wrong type are found per file, so only that file is dropped
+    Value::Table(layer.clone())
+        .try_into::<Settings>()
+        .map_err(|e| invalid(&e.message()))?;
+    Ok(Some(layer))
+}
+
+// tables are merged key by key, everything else is replaced by the upper layer
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(layer_table)) => {
+                merge(base_table, layer_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // layer files in a directory of their own, named by their place in `layers`
+    fn load(name: &str, layers: &[&str]) -> (Settings, Vec<String>) {
+        let dir = env::temp_dir().join(format!("elarge-settings-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut paths: Vec<PathBuf> = layers
+            .iter()
+            .enumerate()
+            .map(|(idx, text)| {
+                let path = dir.join(format!("{}.toml", idx));
+                fs::write(&path, text).unwrap();
+                path
+            })
+            .collect();
+        paths.push(dir.join("missing.toml"));
+        let loaded = Settings::load_layers(&paths);
+        fs::remove_dir_all(&dir).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn later_layers_win_key_by_key() {
+        let (settings, problems) = load(
+            "merge",
+            &[
+                "[editor]\ntab_width = 8\nscrolloff = 1\n[keys.normal]\nx = \"undo\"",
+                "[editor]\ntab_width = 2\n[keys.normal]\ny = \"redo\"",
+            ],
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(settings.editor.tab_width, 2);
+        assert_eq!(settings.editor.scrolloff, 1);
+        assert_eq!(settings.editor.indent_width, 4);
+        assert_eq!(settings.keys.normal.len(), 2);
+    }
+
+    #[test]
+    fn broken_layers_are_left_out() {
+        let (settings, problems) = load(
+            "broken",
+            &[
+                "[editor]\ntab_width = 8",
+                "[editor\nscrolloff = 1",
+                "[editor]\nscrolloff = \"many\"",
+                "[editor]\nexpand_tab = false",
+            ],
+        );
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("1.toml"));
+        assert!(problems[1].contains("2.toml"));
+        assert_eq!(settings.editor.tab_width, 8);
+        assert_eq!(settings.editor.scrolloff, 3);
+        assert!(!settings.editor.expand_tab);
+    }
+}
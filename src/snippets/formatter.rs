@@ -1,5 +1,4 @@
-use std::ops::Range;
-
+#[allow(dead_code)] // placeholder for configurable formatting rules
 struct Rules {
     data: String,
 }
@@ -1,14 +1,18 @@
-use crossterm::event::KeyCode;
-use serde::{Deserialize, Serialize, de};
-use std::io::{self, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::ops::Range;
 
-use crate::editor::{Document, Mode};
+use ropey::Rope;
+
+use crate::editor::{Document, Mode, PasteAt};
+use crate::register::Yank;
+use crate::settings::FunctionSettings;
+use crate::transaction::{Assoc, TranSaction};
 
 use super::grammer::{Surround, Token};
 use super::snippet::SnippetState;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 struct TokenTable {
     token: Token,
     token_range: Range<usize>,
@@ -17,7 +21,53 @@ struct TokenTable {
     string_buff: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl TokenTable {
+    fn map_ranges(&mut self, transaction: &TranSaction, is_syntax: bool) {
+        // typing at the edge of a value extends it, typing next to syntax does not.
+        // syntax inserted by the snippet itself never extends a value
+        let value_end = match is_syntax {
+            true => Assoc::Before,
+            false => Assoc::After,
+        };
+        self.token_range = transaction.map_range(&self.token_range, Assoc::After, Assoc::Before);
+        self.value_range = transaction.map_range(&self.value_range, Assoc::Before, value_end);
+    }
+
+    // where the value is, or where it will be typed when it was not collected yet
+    fn value_start(&self) -> usize {
+        match self.value_range.start < self.token_range.start {
+            true => (self.token_range.start + self.token.to_insert_chunk().value_offset)
+                .min(self.token_range.end),
+            false => self.value_range.start,
+        }
+    }
+
+    fn value_end(&self) -> usize {
+        self.value_start().max(self.value_range.end)
+    }
+
+    // the syntax in front of and behind the value
+    fn syntax_ranges(&self) -> [Range<usize>; 2] {
+        let token = &self.token_range;
+        [
+            token.start..self.value_start().min(token.end),
+            self.value_end().max(token.start)..token.end,
+        ]
+    }
+}
+
+/// What a yanked function carries besides its text: the values that were typed
+/// into it. Pasting builds the syntax around them again, with the snippet
+/// settings of the buffer it is pasted into.
+#[derive(Clone, Default)]
+pub struct FunctionValues {
+    pub name: String,
+    pub param: String,
+    pub result: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 struct Entity {
     name: TokenTable,
     param: TokenTable,
@@ -27,6 +77,138 @@ struct Entity {
 }
 
 impl Entity {
+    // syntax of the default function template adjusted to the configured keywords
+    fn from_settings(settings: &FunctionSettings) -> Self {
+        let mut entity = Self::default();
+        entity.name.token.keyword = settings.keyword.clone();
+        entity.result.token.keyword = settings.result.clone();
+        entity
+    }
+
+    // a finished entity with `values` written out from `start`, and its text
+    fn build(settings: &FunctionSettings, values: &FunctionValues, start: usize) -> (Self, String) {
+        let mut entity = Self::from_settings(settings);
+        let mut text = String::new();
+        for (entity_state, value) in [
+            (EntityState::Name, &values.name),
+            (EntityState::Param, &values.param),
+            (EntityState::Result, &values.result),
+            (EntityState::Body, &values.body),
+        ] {
+            let table = entity.get_mut_table(&entity_state);
+            // an empty result is left out, the same as skipping it while creating
+            if value.is_empty() && table.token.deletable && table.token.skippable {
+                continue;
+            }
+            let chunk = table.token.to_insert_chunk();
+            let chunk_len = chunk.value.chars().count();
+            let chunk_start = start + text.chars().count();
+            let value_start = chunk_start + chunk.value_offset;
+            let value_len = value.chars().count();
+            text.extend(chunk.value.chars().take(chunk.value_offset));
+            text.push_str(value);
+            text.extend(chunk.value.chars().skip(chunk.value_offset));
+            // syntax around the value grows with it, syntax in front of it does not
+            let token_len = match chunk.value_offset < chunk_len {
+                true => chunk_len + value_len,
+                false => chunk_len,
+            };
+            table.token_range = chunk_start..chunk_start + token_len;
+            table.value_range = value_start..value_start + value_len;
+            table.value = value.clone();
+        }
+        (entity, text)
+    }
+
+    fn values(&self, text: &Rope) -> FunctionValues {
+        let value = |table: &TokenTable| match table.token_range.is_empty()
+            || table.value_range.end > text.len_chars()
+        {
+            true => String::new(),
+            false => text.slice(table.value_range.clone()).to_string(),
+        };
+        FunctionValues {
+            name: value(&self.name),
+            param: value(&self.param),
+            result: value(&self.result),
+            body: value(&self.body),
+        }
+    }
+
+    fn tables(&self) -> [&TokenTable; 4] {
+        [&self.name, &self.param, &self.result, &self.body]
+    }
+
+    fn map_ranges(&mut self, transaction: &TranSaction, is_syntax: bool) {
+        for table in [
+            &mut self.name,
+            &mut self.param,
+            &mut self.result,
+            &mut self.body,
+            &mut self.doc,
+        ] {
+            table.map_ranges(transaction, is_syntax);
+        }
+    }
+
+    // text covered by the whole function, from its keyword to the closing syntax
+    fn span(&self) -> Range<usize> {
+        let start = self.name.token_range.start;
+        let end = self
+            .tables()
+            .iter()
+            .map(|table| table.token_range.end.max(table.value_range.end))
+            .max()
+            .unwrap_or(start);
+        start..end
+    }
+
+    // value positions of the components that are in the text, in text order
+    fn component_starts(&self) -> Vec<usize> {
+        self.tables()
+            .iter()
+            .filter(|table| !table.token_range.is_empty())
+            .map(|table| table.value_start())
+            .collect()
+    }
+
+    // start of every parameter, split at commas outside of brackets
+    fn param_starts(&self, text: &Rope) -> Vec<usize> {
+        let range = &self.param.value_range;
+        if self.param.token_range.is_empty() || range.end > text.len_chars() {
+            return Vec::new();
+        }
+        let brackets = [
+            KEYBOARD_PAIRS.parentheses,
+            KEYBOARD_PAIRS.curly_braces,
+            KEYBOARD_PAIRS.square_brackets,
+            KEYBOARD_PAIRS.angle_brackets,
+        ];
+        let mut starts = Vec::new();
+        let mut depth = 0_usize;
+        let mut param_start = true;
+        for (offset, c) in text.slice(range.clone()).chars().enumerate() {
+            if param_start && !c.is_whitespace() {
+                starts.push(range.start + offset);
+                param_start = false;
+            }
+            match c {
+                ',' if depth == 0 => param_start = true,
+                c if brackets.iter().any(|pair| pair.open == c) => depth += 1,
+                c if brackets.iter().any(|pair| pair.close == c) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        starts
+    }
+
+    // all of its syntax was deleted
+    fn is_removed(&self) -> bool {
+        self.tables()
+            .iter()
+            .all(|table| table.token_range.is_empty())
+    }
+
     fn get_mut_table(&mut self, entity_state: &EntityState) -> &mut TokenTable {
         match entity_state {
             EntityState::None => panic!(),
@@ -36,7 +218,7 @@ impl Entity {
             EntityState::Body => &mut self.body,
         }
     }
-    fn get_ref_table(&mut self, entity_state: &EntityState) -> &TokenTable {
+    fn get_ref_table(&self, entity_state: &EntityState) -> &TokenTable {
         match entity_state {
             EntityState::None => panic!(),
             EntityState::Name => &self.name,
@@ -47,8 +229,20 @@ impl Entity {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+// motions that follow the structure of the snippets instead of the text
+#[derive(Clone, Copy, PartialEq)]
+pub enum EntityMotion {
+    NextFunction,
+    PrevFunction,
+    NextComponent, // name, params, result and body of every function
+    PrevComponent,
+    NextParam,
+    PrevParam,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
 pub enum EntityState {
+    #[default]
     None = 0,
     Name = 1,
     Param = 2,
@@ -56,46 +250,18 @@ pub enum EntityState {
     Body = 4,
 }
 
-impl Default for EntityState {
-    fn default() -> Self {
-        Self::None
-    }
-}
-impl TryFrom<KeyCode> for EntityState {
-    type Error = io::Error;
-    fn try_from(value: KeyCode) -> Result<Self, Self::Error> {
-        match value {
-            KeyCode::Char('n') => Ok(EntityState::Name),
-            KeyCode::Char('p') => Ok(EntityState::Param),
-            KeyCode::Char('r') => Ok(EntityState::Result),
-            KeyCode::Char('b') => Ok(EntityState::Body),
-            _ => Err(Error::new(
-                io::ErrorKind::InvalidInput,
-                "invalid cursor direction",
-            )),
-        }
-    }
-}
-
-#[derive(Deserialize, Serialize)]
-enum InsertState {
-    None,
-    Enter,
-    Exit,
-}
-impl Default for InsertState {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 #[derive(Deserialize, Serialize)]
 pub struct EntityManager {
-    entity: Entity,
+    entities: Vec<Entity>, // live entities, the one being created is always last
     state: EntityState,
     order: [EntityState; 5], // in which order collet the user input
-    insert_state: InsertState,
     char_buffer: Vec<char>,
+    revision: Option<usize>, // history revision of the running creation flow
+    synced_revision: usize,
+    #[serde(skip)]
+    snapshots: HashMap<usize, Vec<Entity>>, // entities as they were at each revision
+    #[serde(skip)]
+    recorded: bool, // a value was recorded without an edit, the snapshot is behind
 }
 
 impl EntityManager {
@@ -103,7 +269,34 @@ impl EntityManager {
     fn reset_state(&mut self, doc: &mut Document, snippet_state: &mut SnippetState) {
         *snippet_state = SnippetState::None;
         doc.mode = Mode::Insert;
-        self.insert_state = InsertState::None
+        // whole creation flow is a single undo step
+        doc.history.end_group();
+    }
+
+    // drops an unfinished creation flow, its text is already gone
+    fn cancel_state(&mut self, snippet_state: &mut SnippetState) {
+        *snippet_state = SnippetState::None;
+        self.state = EntityState::None;
+        self.char_buffer.clear();
+    }
+
+    // insert mode was left halfway: what was typed for the current part becomes
+    // its value and the flow stops there, closing its undo step
+    pub fn stop(&mut self, doc: &mut Document, snippet_state: &mut SnippetState) {
+        if Self::check_snippet_state(snippet_state).is_err() {
+            return;
+        }
+        let state = self.state;
+        if state != EntityState::None {
+            self.update_entity_range(doc, false);
+            let mut char_buffer = std::mem::take(&mut self.char_buffer);
+            let table = self.get_mut_entity().get_mut_table(&state);
+            table.value = Self::get_buffer(doc.cursor_get_idx(), &mut char_buffer, table);
+            self.recorded = true;
+        }
+        self.cancel_state(snippet_state);
+        self.revision = None;
+        doc.history.end_group();
     }
 
     fn get_buffer(
@@ -112,8 +305,9 @@ impl EntityManager {
         table: &mut TokenTable,
     ) -> String {
         let res: String = char_buffer.iter().collect();
+        // ranges are char indices, the buffer may hold multi byte chars
         let insert_range = Range {
-            start: cursor_idx - res.len(),
+            start: cursor_idx - res.chars().count(),
             end: cursor_idx,
         };
         table.value_range = insert_range;
@@ -125,11 +319,67 @@ impl EntityManager {
         let is_match = matches!(snippet_state, SnippetState::Create | SnippetState::Delete);
         match is_match {
             true => Ok(()),
-            false => return Err(()),
+            false => Err(()),
+        }
+    }
+
+    // entity being created
+    fn get_mut_entity(&mut self) -> &mut Entity {
+        if self.entities.is_empty() {
+            self.entities.push(Entity::default());
+        }
+        let last = self.entities.len() - 1;
+        &mut self.entities[last]
+    }
+
+    // entity under the cursor, no fallback
+    fn get_entity_at(&self, idx: usize) -> Option<&Entity> {
+        self.entities.iter().find(|entity| {
+            let span = entity.span();
+            span.contains(&idx) || span.end == idx
+        })
+    }
+
+    // moves every stored range through the edits made since the last call,
+    // must run before new ranges are recorded
+    fn update_entity_range(&mut self, doc: &mut Document, is_syntax: bool) {
+        for transaction in doc.changes.drain(..) {
+            for entity in &mut self.entities {
+                entity.map_ranges(&transaction, is_syntax);
+            }
         }
     }
-    fn update_entity_range(&mut self) {
-        // used when entity is modified after creating
+
+    // brings entities in line with the document after every input
+    fn sync(&mut self, doc: &mut Document, creating: bool) {
+        let revision = doc.history.current_revision();
+        // cursor moves, resizes and the like leave the entities as they are
+        let committed = !doc.changes.is_empty();
+        match self.snapshots.get(&revision) {
+            // undo/redo: ranges that collapsed on the way can not be recovered by
+            // mapping, so take the entities as they were at that revision
+            Some(snapshot) if revision != self.synced_revision => {
+                self.entities = snapshot.clone();
+                doc.changes.clear();
+            }
+            _ => self.update_entity_range(doc, false),
+        }
+
+        let in_progress = match creating {
+            true => self.entities.pop(),
+            false => None,
+        };
+        self.entities.retain(|entity| !entity.is_removed());
+        self.entities.extend(in_progress);
+
+        // edits merged into a revision update its snapshot, a new revision gets one
+        if committed || self.recorded || !self.snapshots.contains_key(&revision) {
+            self.recorded = false;
+            let kept = doc.history.revision_count();
+            self.snapshots.retain(|revision, _| *revision < kept);
+            self.snapshots.insert(revision, self.entities.clone());
+        }
+        self.synced_revision = revision;
     }
 }
 
@@ -147,33 +397,33 @@ impl EntityManager {
     }
 
     pub fn insert_chunk(&mut self, doc: &mut Document, snippet_state: &mut SnippetState) {
+        let previous_state = self.state;
         let next_state = self.get_next_state(); // convert to referen option later
         if next_state == EntityState::None {
             self.reset_state(doc, snippet_state);
             return;
         }
-        let table = self.entity.get_mut_table(&next_state);
+        self.update_entity_range(doc, false);
+        // next chunk goes right after the previous one and its value
+        let insert_idx = match previous_state {
+            EntityState::None => doc.cursor_get_idx(),
+            state => {
+                let previous = self.get_mut_entity().get_mut_table(&state);
+                previous.token_range.end.max(previous.value_range.end)
+            }
+        };
+        let table = self.get_mut_entity().get_mut_table(&next_state);
         let insert_chunk = table.token.to_insert_chunk();
 
-        match self.insert_state {
-            // triggered when space is present
-            InsertState::Enter => {
-                doc.cursor.pos.x += insert_chunk.cursor_jump.enter;
-                self.insert_state = InsertState::Exit;
-            }
-            InsertState::Exit => {
-                doc.cursor.pos.x += insert_chunk.cursor_jump.exit;
-                self.insert_state = InsertState::Enter;
-            }
-            _ => {}
-        }
-        doc.cursor_pos_insert(&insert_chunk.value);
-        let cursor_idx = doc.cursor_get_idx(); // use this to delete insert_chunk later
+        doc.cursor_idx_insert(insert_idx, &insert_chunk.value);
+        self.update_entity_range(doc, true);
         let token_range = Range {
-            start: cursor_idx - insert_chunk.value.len(),
-            end: cursor_idx,
+            start: insert_idx,
+            end: insert_idx + insert_chunk.value.chars().count(),
         };
-        table.token_range = token_range;
+        self.get_mut_entity().get_mut_table(&next_state).token_range = token_range;
+        // cursor lands where the value of the chunk is typed
+        doc.cursor_set_pos(doc.cursor_get_loc(insert_idx + insert_chunk.value_offset));
         doc.mode = Mode::Insert
     }
 
@@ -186,25 +436,35 @@ impl EntityManager {
     ) -> Result<(), ()> {
         Self::check_snippet_state(snippet_state)?;
         if character == ' ' {
-            let table: &mut TokenTable = self.entity.get_mut_table(&self.state);
+            let state = self.state;
+            let is_empty = self.char_buffer.is_empty();
+            let table: &mut TokenTable = self.get_mut_entity().get_mut_table(&state);
             let token: &Token = &table.token;
 
-            match self.char_buffer.is_empty() {
-                true => match (token.deletable, token.skippable) {
-                    (true, true) => doc.editor.text.remove(table.token_range.clone()),
+            if is_empty {
+                match (token.deletable, token.skippable) {
+                    (true, true) => {
+                        let token_range = table.token_range.clone();
+                        doc.cursor_set_pos(doc.cursor_get_loc(token_range.start));
+                        doc.remove(token_range);
+                    }
                     (true, false) => {}
                     (false, true) => {}
                     (false, false) => {
                         *system_msg = Some("Value is Required in order to progress".to_string());
                         return Ok(());
                     }
-                },
-                false => {}
+                }
             }
 
             *system_msg = Some("None".to_string());
-            let data = Self::get_buffer(doc.cursor_get_idx(), &mut self.char_buffer, table);
+            self.update_entity_range(doc, false);
+            let mut char_buffer = std::mem::take(&mut self.char_buffer);
+            let table = self.get_mut_entity().get_mut_table(&state);
+            let data = Self::get_buffer(doc.cursor_get_idx(), &mut char_buffer, table);
             table.value = data;
+            self.recorded = true;
+            self.char_buffer = char_buffer;
             self.insert_chunk(doc, snippet_state);
             Ok(())
         } else {
@@ -212,27 +472,51 @@ impl EntityManager {
             Err(())
         }
     }
-}
 
-// used for key combinations
-#[derive(Deserialize, Serialize)]
-pub enum SnippetCommand {
-    SnippetState(SnippetState),
-    EntityState(EntityState),
-    None,
+    // backspace, C-w and C-u while a part is collected take back what was typed
+    // for it, the syntax around it stays
+    pub fn uncollect(
+        &mut self,
+        amount: Uncollect,
+        doc: &mut Document,
+        snippet_state: &mut SnippetState,
+        system_msg: &mut Option<String>,
+    ) -> Result<(), ()> {
+        Self::check_snippet_state(snippet_state)?;
+        let typed = self.char_buffer.len();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let keep = match amount {
+            Uncollect::Char => typed.saturating_sub(1),
+            // the buffer never holds a space, words end where the char class changes
+            Uncollect::Word => {
+                let last = self.char_buffer.last().map(is_word);
+                let word = self.char_buffer.iter().rev();
+                typed - word.take_while(|c| Some(is_word(c)) == last).count()
+            }
+            Uncollect::All => 0,
+        };
+        if keep == typed {
+            *system_msg = Some("Nothing typed for this part yet".to_string());
+            return Ok(());
+        }
+        let cursor_idx = doc.cursor_get_idx();
+        doc.remove(cursor_idx - (typed - keep)..cursor_idx);
+        self.char_buffer.truncate(keep);
+        Ok(())
+    }
 }
 
-impl Default for SnippetCommand {
-    fn default() -> Self {
-        Self::None
-    }
+// how much of the part being collected an editing key takes back
+pub enum Uncollect {
+    Char,
+    Word,
+    All,
 }
 
 #[derive(Deserialize, Serialize, Default)]
 pub struct Snippet {
     pub snippet_state: SnippetState,
     pub entity_manager: EntityManager,
-    pub command_buffer: Vec<SnippetCommand>,
     pub notifications: Option<String>,
 }
 impl Snippet {
@@ -242,9 +526,224 @@ impl Snippet {
             true => {}
             false => return,
         }
-        self.entity_manager.insert_state = InsertState::Enter;
+        // the parts of a function are typed in one place, other cursors would
+        // get text the function does not know about
+        doc.cursor.selection.keep_primary();
+        doc.cursor.sync_pos(&doc.editor.text);
+        doc.history.begin_group();
+        self.entity_manager.update_entity_range(doc, false);
+        self.entity_manager
+            .entities
+            .push(Entity::from_settings(&doc.settings.snippet.function));
         self.entity_manager
             .insert_chunk(doc, &mut self.snippet_state);
+        self.entity_manager.revision = Some(doc.history.current_revision());
+    }
+
+    // keeps entities in line with edits and undo/redo, called after every input
+    pub fn sync(&mut self, doc: &mut Document) {
+        let manager = &mut self.entity_manager;
+        let mut creating = self.snippet_state == SnippetState::Create;
+        if creating
+            && let Some(revision) = manager.revision
+            && !doc.history.is_applied(revision)
+        {
+            // creation was undone, the unfinished flow is cancelled with it
+            manager.cancel_state(&mut self.snippet_state);
+            manager.revision = None;
+            creating = false;
+        }
+        manager.sync(doc, creating);
+    }
+
+    // span of the entity the cursor is in, no fallback
+    pub fn entity_span_at(&self, idx: usize) -> Option<Range<usize>> {
+        self.entity_manager.get_entity_at(idx).map(Entity::span)
+    }
+
+    // where an entity motion from idx lands, None when there is nothing in that direction
+    pub fn entity_motion(&self, text: &Rope, idx: usize, motion: EntityMotion) -> Option<usize> {
+        let entities = &self.entity_manager.entities;
+        let mut targets: Vec<usize> = match motion {
+            EntityMotion::NextFunction | EntityMotion::PrevFunction => {
+                entities.iter().map(|entity| entity.span().start).collect()
+            }
+            EntityMotion::NextComponent | EntityMotion::PrevComponent => entities
+                .iter()
+                .flat_map(|entity| entity.component_starts())
+                .collect(),
+            EntityMotion::NextParam | EntityMotion::PrevParam => entities
+                .iter()
+                .flat_map(|entity| entity.param_starts(text))
+                .collect(),
+        };
+        // entities are stored in creation order, not text order
+        targets.sort_unstable();
+        match motion {
+            EntityMotion::NextFunction | EntityMotion::NextComponent | EntityMotion::NextParam => {
+                targets.into_iter().find(|target| *target > idx)
+            }
+            _ => targets.into_iter().rev().find(|target| *target < idx),
+        }
+    }
+
+    // the function under the cursor as a linewise yank that also carries its values
+    pub fn yank_function(&self, text: &Rope, idx: usize) -> Option<Yank> {
+        let span = self.entity_span_at(idx)?;
+        let entity = self
+            .entity_manager
+            .entities
+            .iter()
+            .find(|entity| entity.span() == span)?;
+        let mut function = text.slice(span.clone()).to_string();
+        function.push('\n');
+        Some(Yank {
+            values: vec![function],
+            linewise: true,
+            functions: vec![entity.values(text)],
+        })
+    }
+
+    // writes the functions out on their own lines above or below the cursor line,
+    // each one becomes a live entity again
+    pub fn paste_functions(
+        &mut self,
+        doc: &mut Document,
+        functions: &[FunctionValues],
+        at: PasteAt,
+    ) {
+        if self.snippet_state == SnippetState::Create {
+            self.notifications = Some("finish the function being created first".to_string());
+            return;
+        }
+        self.entity_manager.update_entity_range(doc, false);
+        let text = &doc.editor.text;
+        let row = text.char_to_line(doc.cursor_get_idx());
+        let line_start = text.line_to_char(row);
+        let (idx, mut inserted) = match at {
+            PasteAt::After => {
+                let next_line = line_start + text.line(row).len_chars();
+                let has_break = next_line > line_start && text.char(next_line - 1) == '\n';
+                match has_break || next_line == 0 {
+                    true => (next_line, String::new()),
+                    // the last line does not end in a line break yet
+                    false => (next_line, String::from("\n")),
+                }
+            }
+            PasteAt::Before | PasteAt::Cursor => (line_start, String::new()),
+        };
+        let mut entities = Vec::new();
+        for values in functions {
+            let start = idx + inserted.chars().count();
+            let (entity, function) = Entity::build(&doc.settings.snippet.function, values, start);
+            inserted.push_str(&function);
+            inserted.push('\n');
+            entities.push(entity);
+        }
+        doc.history.end_group();
+        doc.cursor_idx_insert(idx, &inserted);
+        self.entity_manager.update_entity_range(doc, true);
+        if let Some(first) = entities.first() {
+            doc.cursor_set_pos(doc.cursor_get_loc(first.name.value_range.start));
+        }
+        self.entity_manager.entities.extend(entities);
+    }
+
+    // text object of a component of the function under the cursor: its value, or
+    // the value with its syntax when `around`
+    pub fn component_object(
+        &self,
+        idx: usize,
+        entity_state: EntityState,
+        around: bool,
+    ) -> Option<Range<usize>> {
+        if entity_state == EntityState::None {
+            return None;
+        }
+        let span = self.entity_span_at(idx)?;
+        let entity = self
+            .entity_manager
+            .entities
+            .iter()
+            .find(|entity| entity.span() == span)?;
+        let table = entity.get_ref_table(&entity_state);
+        if table.token_range.is_empty() {
+            return None;
+        }
+        match around {
+            true => Some(
+                table.token_range.start.min(table.value_range.start)
+                    ..table.token_range.end.max(table.value_range.end),
+            ),
+            false => Some(table.value_start()..table.value_start().max(table.value_range.end)),
+        }
+    }
+
+    // deletions may not take syntax a function needs: what is left of `range` in
+    // front of it, or behind it for a `backward` deletion. None when nothing is
+    pub fn guard_deletion(&self, range: Range<usize>, backward: bool) -> Option<Range<usize>> {
+        let mut range = range;
+        let protected = self
+            .entity_manager
+            .entities
+            .iter()
+            .flat_map(|entity| entity.tables())
+            .filter(|table| !table.token.deletable && !table.token_range.is_empty())
+            .flat_map(|table| table.syntax_ranges());
+        for syntax in protected {
+            if syntax.is_empty() || syntax.start >= range.end || syntax.end <= range.start {
+                continue;
+            }
+            match backward {
+                true => range.start = syntax.end.max(range.start),
+                false => range.end = syntax.start.min(range.end),
+            }
+        }
+        (range.start < range.end).then_some(range)
+    }
+
+    // where backspace goes on after running into syntax: the end of the value before idx
+    pub fn prev_value_end(&self, idx: usize) -> Option<usize> {
+        self.entity_manager
+            .entities
+            .iter()
+            .flat_map(|entity| entity.tables())
+            .filter(|table| !table.token_range.is_empty())
+            .map(|table| table.value_end())
+            .filter(|end| *end < idx)
+            .max()
+    }
+
+    // a line broken inside a body, on the line the body opens on, goes one level deeper
+    pub fn indents_body(&self, text: &Rope, idx: usize) -> bool {
+        self.component_object(idx, EntityState::Body, false)
+            .is_some_and(|body| {
+                (body.start..=body.end).contains(&idx)
+                    && text.char_to_line(body.start) == text.char_to_line(idx)
+            })
+    }
+
+    // the body of the function under the cursor, or all of it and its line break
+    pub fn function_object(&self, text: &Rope, idx: usize, around: bool) -> Option<Range<usize>> {
+        if !around {
+            return self.component_object(idx, EntityState::Body, false);
+        }
+        let span = self.entity_span_at(idx)?;
+        match text.get_char(span.end) {
+            Some('\n') => Some(span.start..span.end + 1),
+            _ => Some(span),
+        }
+    }
+
+    // text ranges of every entity with the theme scope they are drawn with,
+    // values come last so they are drawn over the syntax around them
+    pub fn highlight_ranges(&self) -> Vec<(Range<usize>, &'static str)> {
+        let entities = &self.entity_manager.entities;
+        let tables = || entities.iter().flat_map(|entity| entity.tables());
+        tables()
+            .map(|table| (table.token_range.clone(), "entity.token"))
+            .chain(tables().map(|table| (table.value_range.clone(), "entity.value")))
+            .collect()
     }
 
     pub fn is_trigger_state(&self) -> bool {
@@ -254,49 +753,49 @@ impl Snippet {
         )
     }
 
-    // used in normal mode. starm method should be later moved here
-    pub fn check_command(&mut self, snippet_command: SnippetCommand, doc: &mut Document) {
-        self.command_buffer.push(snippet_command);
-        if self.command_buffer.len() == 2_usize {
-            // we got the both
-            if let (
-                SnippetCommand::SnippetState(snippet_state),
-                SnippetCommand::EntityState(entity_state),
-            ) = (&self.command_buffer[0], &self.command_buffer[1])
-            {
-                let table = self.entity_manager.entity.get_ref_table(entity_state);
-
-                let mut swap_action = || {
-                    let jump_index: usize = table.value_range.start;
-                    doc.cursor_set_pos(doc.cursor_get_loc(jump_index));
-                    doc.editor.text.remove(table.value_range.clone());
-                    doc.mode = Mode::Insert;
-                };
-
-                match snippet_state {
-                    SnippetState::Create => {}
-                    SnippetState::Delete => {
-                        if !table.token.skippable {
-                            swap_action();
-                        } else {
-                            doc.editor.text.remove(table.value_range.clone());
-                        }
-                    }
-                    SnippetState::Swap => {
-                        swap_action();
-                    }
-                    SnippetState::None => {}
-                    _ => {}
+    // an action typed in normal mode with the entity key that followed it
+    pub fn act(&mut self, action: SnippetState, entity_state: EntityState, doc: &mut Document) {
+        let Some(entity) = self.entity_manager.get_entity_at(doc.cursor_get_idx()) else {
+            self.notifications = Some("No entity to act on".to_string());
+            return;
+        };
+        let table = entity.get_ref_table(&entity_state);
+        // a skipped or removed part has no text left, its value range is stale
+        if table.token_range.is_empty() {
+            self.notifications = Some("This part of the function is not there".to_string());
+            return;
+        }
+        // not part of a creation flow that might still be open
+        doc.history.end_group();
+
+        let mut swap_action = || {
+            let jump_index: usize = table.value_range.start;
+            doc.cursor_set_pos(doc.cursor_get_loc(jump_index));
+            doc.remove(table.value_range.clone());
+            doc.mode = Mode::Insert;
+        };
+
+        match action {
+            SnippetState::Create => {}
+            SnippetState::Delete => {
+                if !table.token.skippable {
+                    swap_action();
+                } else {
+                    doc.remove(table.value_range.clone());
                 }
-            };
-            self.command_buffer.clear();
+            }
+            SnippetState::Swap => {
+                swap_action();
+            }
+            SnippetState::None => {}
+            _ => {}
         }
     }
 }
 impl Default for EntityManager {
     fn default() -> Self {
         Self {
-            entity: Default::default(),
+            entities: Vec::new(),
             state: Default::default(),
             order: [
                 EntityState::None,
@@ -306,7 +805,10 @@ impl Default for EntityManager {
                 EntityState::Body,
             ],
             char_buffer: Default::default(),
-            insert_state: Default::default(),
+            revision: None,
+            synced_revision: 0,
+            snapshots: HashMap::new(),
+            recorded: false,
         }
     }
 }
@@ -322,6 +824,7 @@ pub struct StringPair {
     pub close: String,
 }
 
+#[allow(dead_code)]
 pub struct KeyboardPairs {
     pub underscore_spaces: CharPair,
     pub spaces: CharPair,
@@ -333,7 +836,7 @@ pub struct KeyboardPairs {
     pub double_quotes: CharPair,
 }
 
-const KEYBOARD_PAIRS: KeyboardPairs = KeyboardPairs {
+pub const KEYBOARD_PAIRS: KeyboardPairs = KeyboardPairs {
     underscore_spaces: CharPair {
         open: '_',
         close: '_',
@@ -368,7 +871,7 @@ const KEYBOARD_PAIRS: KeyboardPairs = KeyboardPairs {
     },
 };
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Pair {
     CharPair(CharPair),
     StringPair(StringPair),
@@ -454,3 +957,151 @@ impl Default for Entity {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::CursorRange;
+
+    // "fn main(a) {x}" as a live entity, without a result
+    fn setup(before: &str, after: &str) -> (Document, Snippet) {
+        let mut doc = Document::new("");
+        let values = FunctionValues {
+            name: "main".to_string(),
+            param: "a".to_string(),
+            result: String::new(),
+            body: "x".to_string(),
+        };
+        let start = before.chars().count();
+        let (entity, function) = Entity::build(&doc.settings.snippet.function, &values, start);
+        doc.editor.text = Rope::from(format!("{}{}{}", before, function, after));
+        let mut snippet = Snippet::default();
+        snippet.entity_manager.entities.push(entity);
+        snippet.sync(&mut doc);
+        (doc, snippet)
+    }
+
+    fn entity(snippet: &Snippet) -> &Entity {
+        &snippet.entity_manager.entities[0]
+    }
+
+    fn values(doc: &Document, snippet: &Snippet) -> [String; 4] {
+        let values = entity(snippet).values(&doc.editor.text);
+        [values.name, values.param, values.result, values.body]
+    }
+
+    fn set_cursor(doc: &mut Document, idx: usize) {
+        doc.cursor_set_pos(doc.cursor_get_loc(idx));
+    }
+
+    #[test]
+    fn edits_before_move_the_ranges() {
+        let (mut doc, mut snippet) = setup("", "\n");
+        doc.cursor_idx_insert(0, "// top\n");
+        snippet.sync(&mut doc);
+        assert_eq!(entity(&snippet).name.token_range, 7..10);
+        assert_eq!(entity(&snippet).name.value_range, 10..14);
+        assert_eq!(entity(&snippet).body.value_range, 19..20);
+        assert_eq!(values(&doc, &snippet), ["main", "a", "", "x"]);
+    }
+
+    #[test]
+    fn typing_at_a_value_edge_extends_it() {
+        let (mut doc, mut snippet) = setup("", "");
+        // right behind "main" and right in front of "x"
+        doc.cursor_idx_insert(7, "_2");
+        doc.cursor_idx_insert(14, "y ");
+        snippet.sync(&mut doc);
+        assert_eq!(values(&doc, &snippet), ["main_2", "a", "", "y x"]);
+        assert_eq!(entity(&snippet).param.token_range, 9..12);
+        assert_eq!(entity(&snippet).span(), 0..18);
+    }
+
+    #[test]
+    fn syntax_inserted_next_to_a_value_stays_out_of_it() {
+        let (_, mut snippet) = setup("", "");
+        let text = Rope::from("fn main(a) {x}");
+        let transaction = TranSaction::insert(&text, 7, "()");
+        snippet.entity_manager.entities[0].map_ranges(&transaction, true);
+        assert_eq!(entity(&snippet).name.value_range, 3..7);
+        assert_eq!(entity(&snippet).param.token_range, 9..12);
+    }
+
+    #[test]
+    fn deleted_entities_come_back_with_undo() {
+        let (mut doc, mut snippet) = setup("", "\nrest");
+        doc.remove(0..15);
+        snippet.sync(&mut doc);
+        assert!(snippet.entity_manager.entities.is_empty());
+
+        doc.undo();
+        snippet.sync(&mut doc);
+        assert_eq!(doc.editor.text, "fn main(a) {x}\nrest");
+        assert_eq!(entity(&snippet).name.value_range, 3..7);
+        assert_eq!(entity(&snippet).body.token_range, 10..14);
+
+        doc.redo();
+        snippet.sync(&mut doc);
+        assert!(snippet.entity_manager.entities.is_empty());
+    }
+
+    #[test]
+    fn snapshots_follow_edits_only() {
+        let (mut doc, mut snippet) = setup("", "");
+        // a snapshot of a revision the history does not have is dropped
+        snippet.entity_manager.snapshots.insert(7, Vec::new());
+        doc.cursor_idx_insert(0, " ");
+        snippet.sync(&mut doc);
+        let snapshots = &snippet.entity_manager.snapshots;
+        assert!(!snapshots.contains_key(&7));
+        assert_eq!(snapshots[&1][0].name.token_range, 1..4);
+
+        // syncing without an edit leaves the snapshot alone
+        snippet.entity_manager.entities.clear();
+        snippet.sync(&mut doc);
+        assert_eq!(snippet.entity_manager.snapshots[&1].len(), 1);
+    }
+
+    #[test]
+    fn creating_keeps_only_the_primary_cursor() {
+        let mut doc = Document::new("\n\n");
+        doc.cursor.selection.push(CursorRange::point(1));
+        let mut snippet = Snippet::default();
+        snippet.start(SnippetState::Create, &mut doc);
+        assert_eq!(doc.cursor.selection.len(), 1);
+        for c in "main ".chars() {
+            if snippet
+                .entity_manager
+                .collect_chunk_buffer(c, &mut doc, &mut snippet.snippet_state, &mut None)
+                .is_err()
+            {
+                doc.insert_at_cursors(&c.to_string());
+            }
+        }
+        // the cursor pushed last is the primary one
+        assert_eq!(doc.editor.text, "\nfn main()\n");
+        assert_eq!(entity(&snippet).name.value, "main");
+        assert_eq!(entity(&snippet).name.value_range, 4..8);
+    }
+
+    #[test]
+    fn acting_outside_of_entities_does_nothing() {
+        let (mut doc, mut snippet) = setup("", "\n\nother");
+        set_cursor(&mut doc, 18);
+        snippet.act(SnippetState::Delete, EntityState::Param, &mut doc);
+        assert_eq!(doc.editor.text, "fn main(a) {x}\n\nother");
+        assert!(snippet.notifications.is_some());
+    }
+
+    #[test]
+    fn acting_on_a_skipped_part_does_nothing() {
+        let (mut doc, mut snippet) = setup("", "");
+        set_cursor(&mut doc, 4);
+        snippet.act(SnippetState::Delete, EntityState::Result, &mut doc);
+        assert_eq!(doc.editor.text, "fn main(a) {x}");
+        assert!(snippet.notifications.is_some());
+
+        snippet.act(SnippetState::Delete, EntityState::Param, &mut doc);
+        assert_eq!(doc.editor.text, "fn main() {x}");
+    }
+}
@@ -2,39 +2,28 @@ use serde::{Deserialize, Serialize};
 
 use super::function::Pair;
 
+#[allow(dead_code)]
 #[derive(Deserialize, Serialize)]
 pub struct StringRange {
     pub start: String,
     pub end: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default, Clone)]
 pub enum Surround {
+    #[default]
     None,
     Left(String),
     Right(String),
     Pair(Pair),
 }
 
-impl Default for Surround {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
-#[derive(Default)]
-pub struct CursorJump {
-    // where cursor should end up when entring/exiting snippet chunks (insert mode)
-    pub enter: usize,
-    pub exit: usize,
-}
-
 pub struct InsertChunk {
     pub value: String,
-    pub cursor_jump: CursorJump,
+    pub value_offset: usize, // chars from the chunk start to where the user value goes
 }
 
-#[derive(Deserialize, Serialize, Default)]
+#[derive(Deserialize, Serialize, Default, Clone)]
 pub struct Token {
     pub keyword: String,
     pub syntax: Surround,
@@ -49,31 +38,27 @@ impl Token {
         // returns what insert should be and position where cursor shoud lend after insert
         let target = &self.keyword;
         let mut res = String::from("");
-        let mut cursor_jump = CursorJump::default();
+        let mut value_offset: Option<usize> = None; // None means end of the chunk
 
         match self.syntax {
             Surround::Left(ref value) => {
                 res.insert_str(0, value);
                 res.push_str(target);
-                cursor_jump.enter += res.len()
             }
             Surround::Right(ref value) => {
                 res.push_str(target);
                 res.push_str(value);
-                cursor_jump.enter += res.len()
             }
             Surround::Pair(ref pair) => match pair {
                 Pair::CharPair(char_pair) => {
                     res.push(char_pair.open);
                     res.push(char_pair.close);
-                    cursor_jump.enter += 1;
-                    cursor_jump.exit += 1;
+                    value_offset = Some(1);
                 }
                 Pair::StringPair(string_pair) => {
                     res.push_str(&string_pair.open);
                     res.push_str(&string_pair.close);
-                    cursor_jump.enter += string_pair.open.len();
-                    cursor_jump.exit += string_pair.close.len()
+                    value_offset = Some(string_pair.open.chars().count());
                 }
             },
             Surround::None => {}
@@ -82,33 +67,30 @@ impl Token {
             Surround::Left(ref value) => {
                 res.insert_str(0, value);
                 res.push_str(target);
-                cursor_jump.exit += value.len() + target.len()
+                value_offset = value_offset.map(|offset| offset + value.chars().count());
             }
             Surround::Right(ref value) => {
                 res.push_str(target);
                 res.push_str(value);
-                cursor_jump.exit += value.len() + target.len()
             }
             Surround::Pair(ref pair) => match pair {
                 Pair::CharPair(char_pair) => {
                     res.push(char_pair.open);
                     res.push_str(target);
                     res.push(char_pair.close);
-                    cursor_jump.enter += res.len() + 1;
                 }
                 Pair::StringPair(string_pair) => {
                     res.push_str(&string_pair.open);
                     res.push_str(target);
                     res.push_str(&string_pair.close);
-                    cursor_jump.enter += res.len() + 1;
                 }
             },
             Surround::None => {}
         }
 
         InsertChunk {
+            value_offset: value_offset.unwrap_or(res.chars().count()),
             value: res,
-            cursor_jump,
         }
     }
 }
@@ -0,0 +1,600 @@
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use ropey::Rope;
+
+use crate::{
+    editor::{Document, Mode},
+    files, search, settings,
+    transaction::{Change, TranSaction},
+};
+
+const MAX_FILE_SIZE: u64 = 1 << 20; // bigger files are skipped by project wide substitution
+
+// where `:s` looks for matches
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    Selection, // the selections, or the cursor line when nothing is selected
+    Buffer,    // :%s
+    Project,   // :ps, every file under the project root
+}
+
+struct Replacement {
+    range: Range<usize>, // chars in the text of its file
+    row: usize,
+    with: String,
+    accepted: Option<bool>, // answered in confirm mode
+}
+
+struct FileReplacements {
+    path: Option<PathBuf>, // None is the open buffer
+    text: Rope,            // as it was when the matches were found
+    replacements: Vec<Replacement>,
+}
+
+// what `:ps` wrote to a file other than the buffer, `:pundo` takes it back
+pub struct FileEdit {
+    path: PathBuf,
+    undo: TranSaction, // inverse of the replacements
+    written: Rope,
+}
+
+/// Every replacement a `:s` would make, shown in a preview before anything is
+/// written. In confirm mode each one is answered on its own.
+pub struct Substitution {
+    files: Vec<FileReplacements>,
+    confirm_each: bool,
+    current: usize, // replacement asked about in confirm mode, counted across files
+}
+
+// one entry of the preview pane
+pub struct PreviewLine {
+    pub location: String,
+    pub line: String, // the line with this replacement made
+    pub accepted: Option<bool>,
+}
+
+impl Substitution {
+    fn replacements(&self) -> impl Iterator<Item = (&FileReplacements, &Replacement)> {
+        self.files
+            .iter()
+            .flat_map(|file| file.replacements.iter().map(move |found| (file, found)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.replacements().count()
+    }
+
+    pub fn preview(&self) -> Vec<PreviewLine> {
+        self.replacements()
+            .map(|(file, found)| {
+                let line_start = file.text.line_to_char(found.row);
+                let line = file.text.line(found.row).to_string();
+                let mut chars: Vec<char> = line.trim_end_matches(['\r', '\n']).chars().collect();
+                let from = (found.range.start - line_start).min(chars.len());
+                let to = (found.range.end - line_start).min(chars.len());
+                chars.splice(from..to, found.with.chars());
+                let name = match &file.path {
+                    Some(path) => path.display().to_string(),
+                    None => "buffer".to_string(),
+                };
+                PreviewLine {
+                    location: format!("{}:{}", name, found.row + 1),
+                    line: chars.into_iter().collect(),
+                    accepted: found.accepted,
+                }
+            })
+            .collect()
+    }
+
+    pub fn confirm_each(&self) -> bool {
+        self.confirm_each
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    // where the replacement asked about is in the open buffer, to show it in place
+    pub fn current_in_buffer(&self) -> Option<usize> {
+        let (file, found) = self.replacements().nth(self.current)?;
+        file.path.is_none().then_some(found.range.start)
+    }
+
+    fn answer(&mut self, accepted: bool) {
+        let current = self.current;
+        if let Some(found) = self
+            .files
+            .iter_mut()
+            .flat_map(|file| file.replacements.iter_mut())
+            .nth(current)
+        {
+            found.accepted = Some(accepted);
+        }
+        self.current += 1;
+    }
+
+    fn is_answered(&self) -> bool {
+        self.current >= self.len()
+    }
+}
+
+// `/pattern/replacement/flags`, any char after the command name works as the delimiter
+fn parse(text: &str) -> Result<(String, String, String), String> {
+    let mut chars = text.chars();
+    let Some(delimiter) = chars.next().filter(|c| !c.is_alphanumeric() && *c != ' ') else {
+        return Err("expected s/pattern/replacement/flags".to_string());
+    };
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // an escaped delimiter is the plain char, other escapes are for the regex
+                Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    let part = parts.last_mut().unwrap();
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            c if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    if parts.len() < 2 || parts[0].is_empty() {
+        return Err("expected s/pattern/replacement/flags".to_string());
+    }
+    parts.resize(3, String::new());
+    let flags = parts.pop().unwrap_or_default();
+    let replacement = parts.pop().unwrap_or_default();
+    let pattern = parts.pop().unwrap_or_default();
+    Ok((pattern, replacement, flags))
+}
+
+// vim writes groups as \1 and the whole match as &, the regex crate as ${1}
+// and ${0}. A literal $ has to be $$ there, \& and \\ are a literal & and \
+fn to_regex_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                converted.push_str(&format!("${{{}}}", digit));
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                converted.push('\n');
+                chars.next();
+            }
+            ('\\', Some('$')) => {
+                converted.push_str("$$");
+                chars.next();
+            }
+            ('\\', Some(_)) => converted.extend(chars.next()),
+            ('$', _) => converted.push_str("$$"),
+            ('&', _) => converted.push_str("${0}"),
+            (c, _) => converted.push(c),
+        }
+    }
+    converted
+}
+
+fn find_replacements(
+    regex: &Regex,
+    replacement: &str,
+    text: &Rope,
+    ranges: &[Range<usize>],
+    global: bool,
+) -> Vec<Replacement> {
+    let mut found = Vec::new();
+    for range in ranges {
+        let first_row = text.char_to_line(range.start);
+        // a range of whole lines ends at the start of the next one
+        let last = range.end.saturating_sub(1).max(range.start);
+        let last_row = text.char_to_line(last.min(text.len_chars()));
+        for row in first_row..=last_row {
+            let line = search::line_content(text, row);
+            let line_start = text.line_to_char(row);
+            let content = line.to_string();
+            let in_line = regex.captures_iter(&content).filter_map(|captures| {
+                let whole = captures.get(0)?;
+                let start = line_start + line.byte_to_char(whole.start());
+                let end = line_start + line.byte_to_char(whole.end());
+                if start < range.start || end > range.end {
+                    return None;
+                }
+                let mut with = String::new();
+                captures.expand(replacement, &mut with);
+                Some(Replacement {
+                    range: start..end,
+                    row,
+                    with,
+                    accepted: None,
+                })
+            });
+            // like vim, only the first match of a line without the g flag
+            match global {
+                true => found.extend(in_line),
+                false => found.extend(in_line.take(1)),
+            }
+        }
+    }
+    found
+}
+
+// text files under the project root, hidden directories and build output left out
+fn project_files(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    entries.sort();
+    for path in entries {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let skipped = name.is_none_or(|name| name.starts_with('.') || name == "target");
+        if skipped {
+            continue;
+        }
+        if path.is_dir() {
+            project_files(&path, found);
+        } else if fs::metadata(&path).is_ok_and(|meta| meta.len() <= MAX_FILE_SIZE) {
+            found.push(path);
+        }
+    }
+}
+
+fn whole(text: &Rope) -> Range<usize> {
+    0..text.len_chars()
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// runs `:s`, `:%s` or `:ps`, the preview opens when there is anything to replace
+pub fn start(doc: &mut Document, scope: Scope, args: &str) {
+    let (pattern, replacement, flags) = match parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            doc.message = Some(e);
+            return;
+        }
+    };
+    let pattern = match flags.contains('i') {
+        true => format!("(?i){}", pattern),
+        false => pattern,
+    };
+    let regex = match search::compile(&pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            doc.message = Some(format!("invalid pattern: {}", e));
+            return;
+        }
+    };
+    let replacement = to_regex_replacement(&replacement);
+    let global = flags.contains('g');
+
+    let text = &doc.editor.text;
+    let ranges: Vec<Range<usize>> = match scope {
+        Scope::Selection if doc.cursor.selection.ranges().iter().any(|r| !r.is_empty()) => doc
+            .cursor
+            .selection
+            .ranges()
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| range.chars())
+            .collect(),
+        Scope::Selection => {
+            let row = doc.cursor.pos.y;
+            let line_start = text.line_to_char(row);
+            let line = line_start..line_start + text.line(row).len_chars();
+            vec![line]
+        }
+        Scope::Buffer | Scope::Project => vec![whole(text)],
+    };
+    let mut files = vec![FileReplacements {
+        path: None,
+        text: text.clone(),
+        replacements: find_replacements(&regex, &replacement, text, &ranges, global),
+    }];
+    if scope == Scope::Project {
+        let mut paths = Vec::new();
+        project_files(&settings::project_root(), &mut paths);
+        for path in paths {
+            // the open file is searched in the buffer, it may have unsaved changes
+            if doc.path.as_deref().is_some_and(|own| same_file(own, &path)) {
+                continue;
+            }
+            // not utf-8 or with nul bytes, most likely not text
+            let Some(content) = files::read_file(&path)
+                .ok()
+                .filter(|content| !content.contains('\0'))
+            else {
+                continue;
+            };
+            let text = Rope::from_str(&content);
+            let everything = whole(&text);
+            let replacements = find_replacements(
+                &regex,
+                &replacement,
+                &text,
+                std::slice::from_ref(&everything),
+                global,
+            );
+            if !replacements.is_empty() {
+                files.push(FileReplacements {
+                    path: Some(path),
+                    text,
+                    replacements,
+                });
+            }
+        }
+    }
+    files.retain(|file| !file.replacements.is_empty());
+    if files.is_empty() {
+        doc.message = Some(format!("pattern not found: {}", regex.as_str()));
+        return;
+    }
+    doc.substitution = Some(Substitution {
+        files,
+        confirm_each: flags.contains('c'),
+        current: 0,
+    });
+    doc.mode = Mode::Preview;
+    show_current(doc);
+}
+
+// moves the cursor onto the replacement asked about when it is in the buffer
+fn show_current(doc: &mut Document) {
+    let idx = doc
+        .substitution
+        .as_ref()
+        .and_then(|s| s.current_in_buffer());
+    if let Some(idx) = idx {
+        doc.cursor.set_idx(&doc.editor.text, idx);
+    }
+}
+
+// y or enter: the current replacement in confirm mode, everything otherwise
+pub fn accept(doc: &mut Document) {
+    let Some(substitution) = doc.substitution.as_mut() else {
+        return;
+    };
+    match substitution.confirm_each {
+        true => substitution.answer(true),
+        false => return accept_all(doc),
+    }
+    next(doc);
+}
+
+pub fn skip(doc: &mut Document) {
+    let Some(substitution) = doc.substitution.as_mut() else {
+        return;
+    };
+    match substitution.confirm_each {
+        true => substitution.answer(false),
+        // the preview is all or nothing
+        false => return cancel(doc),
+    }
+    next(doc);
+}
+
+pub fn accept_all(doc: &mut Document) {
+    if let Some(substitution) = doc.substitution.as_mut() {
+        while !substitution.is_answered() {
+            substitution.answer(true);
+        }
+    }
+    finish(doc);
+}
+
+// esc: nothing is replaced
+pub fn cancel(doc: &mut Document) {
+    doc.substitution = None;
+    doc.mode = Mode::Normal;
+    doc.message = Some("substitution cancelled".to_string());
+}
+
+fn next(doc: &mut Document) {
+    match doc.substitution.as_ref().is_some_and(|s| s.is_answered()) {
+        true => finish(doc),
+        false => show_current(doc),
+    }
+}
+
+// q, or every replacement answered: writes what was accepted, one transaction per file
+pub fn finish(doc: &mut Document) {
+    let Some(substitution) = doc.substitution.take() else {
+        return;
+    };
+    doc.mode = Mode::Normal;
+    let (mut replaced, mut changed_files, mut errors) = (0, 0, Vec::new());
+    let mut edits = Vec::new();
+    for file in substitution.files {
+        let changes: Vec<Change> = file
+            .replacements
+            .iter()
+            .filter(|found| found.accepted == Some(true))
+            .map(|found| (found.range.start, found.range.end, Some(found.with.clone())))
+            .collect();
+        if changes.is_empty() {
+            continue;
+        }
+        let count = changes.len();
+        let transaction = TranSaction::change(&file.text, changes);
+        match file.path {
+            None => {
+                // a single undo step for the whole buffer
+                doc.history.end_group();
+                doc.apply(&transaction);
+            }
+            Some(path) => {
+                // the matches were found in the text read when the preview opened
+                match files::read_file(&path) {
+                    Ok(current) if file.text == current.as_str() => {}
+                    Ok(_) => {
+                        errors.push(format!("{}: changed on disk, skipped", path.display()));
+                        continue;
+                    }
+                    Err(e) => {
+                        errors.push(format!("{}: {}", path.display(), e));
+                        continue;
+                    }
+                }
+                let mut text = file.text.clone();
+                transaction.apply(&mut text);
+                if let Err(e) = files::write_file(&path, &text) {
+                    errors.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+                edits.push(FileEdit {
+                    path,
+                    undo: transaction.invert(&file.text),
+                    written: text,
+                });
+            }
+        }
+        replaced += count;
+        changed_files += 1;
+    }
+    if !edits.is_empty() {
+        doc.file_edits.push(edits);
+    }
+    doc.message = Some(match errors.is_empty() {
+        true => format!("replaced {} matches in {} files", replaced, changed_files),
+        false => format!(
+            "replaced {} matches, failed: {}",
+            replaced,
+            errors.join(", ")
+        ),
+    });
+}
+
+// :pundo, the files the last `:ps` wrote go back to how they were. The buffer
+// has its own undo step. A file edited since is left alone
+pub fn undo_files(doc: &mut Document) {
+    let Some(edits) = doc.file_edits.pop() else {
+        doc.message = Some("no project substitution to undo".to_string());
+        return;
+    };
+    let (mut restored, mut errors) = (0, Vec::new());
+    for edit in edits {
+        match files::read_file(&edit.path) {
+            Ok(current) if edit.written == current.as_str() => {}
+            Ok(_) => {
+                errors.push(format!("{}: changed on disk, skipped", edit.path.display()));
+                continue;
+            }
+            Err(e) => {
+                errors.push(format!("{}: {}", edit.path.display(), e));
+                continue;
+            }
+        }
+        let mut text = edit.written;
+        edit.undo.apply(&mut text);
+        match files::write_file(&edit.path, &text) {
+            Ok(()) => restored += 1,
+            Err(e) => errors.push(format!("{}: {}", edit.path.display(), e)),
+        }
+    }
+    doc.message = Some(match errors.is_empty() {
+        true => format!("restored {} files", restored),
+        false => format!("restored {} files, failed: {}", restored, errors.join(", ")),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(pattern: &str, replacement: &str, flags: &str) -> (String, String, String) {
+        (
+            pattern.to_string(),
+            replacement.to_string(),
+            flags.to_string(),
+        )
+    }
+
+    #[test]
+    fn parse_parts() {
+        assert_eq!(parse("/a/b/gc"), Ok(parts("a", "b", "gc")));
+        assert_eq!(parse("/a/b"), Ok(parts("a", "b", "")));
+        assert_eq!(parse("/a/"), Ok(parts("a", "", "")));
+        assert_eq!(parse("#a/b#c#"), Ok(parts("a/b", "c", "")));
+    }
+
+    #[test]
+    fn parse_escapes() {
+        // an escaped delimiter is plain, other escapes stay for the regex
+        assert_eq!(parse(r"/a\/b/c\1/"), Ok(parts("a/b", r"c\1", "")));
+        assert_eq!(parse(r"/\d+/x/"), Ok(parts(r"\d+", "x", "")));
+        // a delimiter past the flags is part of them
+        assert_eq!(parse("/a/b/g/"), Ok(parts("a", "b", "g/")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("abc").is_err());
+        assert!(parse(" a b").is_err());
+        assert!(parse("//b/").is_err());
+        assert!(parse("/a").is_err());
+    }
+
+    #[test]
+    fn replacement_conversion() {
+        assert_eq!(to_regex_replacement(r"\1-\2"), "${1}-${2}");
+        assert_eq!(to_regex_replacement(r"a\nb"), "a\nb");
+        assert_eq!(to_regex_replacement("&!"), "${0}!");
+        assert_eq!(to_regex_replacement(r"\&"), "&");
+        assert_eq!(to_regex_replacement(r"\\"), r"\");
+        assert_eq!(to_regex_replacement("$5"), "$$5");
+        assert_eq!(to_regex_replacement(r"a\$b"), "a$$b");
+    }
+
+    #[test]
+    fn matches_leave_line_breaks_alone() {
+        let text = Rope::from("ax\nb  x\r\nx");
+        let found = |pattern: &str, replacement: &str| {
+            let regex = Regex::new(pattern).unwrap();
+            let replacements = find_replacements(&regex, replacement, &text, &[whole(&text)], true);
+            replacements
+                .into_iter()
+                .map(|found| (found.range, found.with))
+                .collect::<Vec<(Range<usize>, String)>>()
+        };
+        assert_eq!(
+            found("x$", "y"),
+            [
+                (1..2, "y".to_string()),
+                (6..7, "y".to_string()),
+                (9..10, "y".to_string())
+            ]
+        );
+        assert_eq!(found(r"\s+", " "), [(4..6, " ".to_string())]);
+    }
+
+    #[test]
+    fn replacement_expands_literally() {
+        let regex = Regex::new(r"(\w+) (\w+)").unwrap();
+        let replace = |replacement: &str| {
+            regex
+                .replace("hello world", to_regex_replacement(replacement))
+                .to_string()
+        };
+        assert_eq!(replace(r"\2 \1"), "world hello");
+        assert_eq!(replace("[&]"), "[hello world]");
+        assert_eq!(replace("$1 costs $5"), "$1 costs $5");
+        assert_eq!(replace(r"\\ \&"), r"\ &");
+    }
+}
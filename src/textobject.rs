@@ -0,0 +1,128 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+use crate::snippets::function::{CharPair, KEYBOARD_PAIRS};
+
+// the delimiters a text object or surround can be made of, by either of its chars
+pub fn pair_for(c: char) -> Option<CharPair> {
+    [
+        KEYBOARD_PAIRS.parentheses,
+        KEYBOARD_PAIRS.curly_braces,
+        KEYBOARD_PAIRS.square_brackets,
+        KEYBOARD_PAIRS.angle_brackets,
+        KEYBOARD_PAIRS.single_quotes,
+        KEYBOARD_PAIRS.double_quotes,
+    ]
+    .into_iter()
+    .find(|pair| pair.open == c || pair.close == c)
+}
+
+// text between the closest pair around idx, with the delimiters when `around`
+pub fn pair_range(text: &Rope, idx: usize, pair: CharPair, around: bool) -> Option<Range<usize>> {
+    let (open, close) = match pair.open == pair.close {
+        true => quote_positions(text, idx, pair.open)?,
+        false => bracket_positions(text, idx, pair)?,
+    };
+    match around {
+        true => Some(open..close + 1),
+        false => Some(open + 1..close),
+    }
+}
+
+// quotes do not nest, they pair up from the start of the line
+fn quote_positions(text: &Rope, idx: usize, quote: char) -> Option<(usize, usize)> {
+    let row = text.char_to_line(idx);
+    let line_start = text.line_to_char(row);
+    let quotes: Vec<usize> = text
+        .line(row)
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| *c == quote)
+        .map(|(offset, _)| line_start + offset)
+        .collect();
+    quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(open, close)| *open <= idx && idx <= *close)
+}
+
+fn bracket_positions(text: &Rope, idx: usize, pair: CharPair) -> Option<(usize, usize)> {
+    // backwards from the char under the cursor, skipping nested pairs
+    let end = (idx + 1).min(text.len_chars());
+    let mut depth = 0_usize;
+    let mut open = None;
+    for (offset, c) in text.chars_at(end).reversed().enumerate() {
+        let position = end - offset - 1;
+        if c == pair.close && position != idx {
+            depth += 1;
+        } else if c == pair.open {
+            if depth == 0 {
+                open = Some(position);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let open = open?;
+    let mut depth = 0_usize;
+    for (offset, c) in text.chars_at(open + 1).enumerate() {
+        if c == pair.open {
+            depth += 1;
+        } else if c == pair.close {
+            if depth == 0 {
+                return Some((open, open + 1 + offset));
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(text: &str, idx: usize, c: char, around: bool) -> Option<String> {
+        let text = Rope::from(text);
+        let range = pair_range(&text, idx, pair_for(c)?, around)?;
+        Some(text.slice(range).to_string())
+    }
+
+    #[test]
+    fn pairs_by_either_char() {
+        assert_eq!(pair_for('(').map(|pair| pair.close), Some(')'));
+        assert_eq!(pair_for('}').map(|pair| pair.open), Some('{'));
+        assert_eq!(pair_for('"').map(|pair| pair.close), Some('"'));
+        assert!(pair_for('x').is_none());
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "f(a, (b), c)";
+        assert_eq!(object(text, 3, '(', false).as_deref(), Some("a, (b), c"));
+        assert_eq!(object(text, 3, ')', true).as_deref(), Some("(a, (b), c)"));
+        // on a delimiter, nested or not
+        assert_eq!(object(text, 5, '(', false).as_deref(), Some("b"));
+        assert_eq!(object(text, 7, '(', false).as_deref(), Some("b"));
+        assert_eq!(object(text, 11, '(', false).as_deref(), Some("a, (b), c"));
+        assert_eq!(object(text, 0, '(', false), None);
+        assert_eq!(
+            object("{\n  x\n}", 4, '{', false).as_deref(),
+            Some("\n  x\n")
+        );
+        assert_eq!(object("(()", 2, '(', false).as_deref(), Some(""));
+        assert_eq!(object("(x", 1, '(', false), None);
+    }
+
+    #[test]
+    fn quotes_pair_up_in_their_line() {
+        let text = "a \"b\" c \"d\"";
+        assert_eq!(object(text, 3, '"', false).as_deref(), Some("b"));
+        assert_eq!(object(text, 2, '"', true).as_deref(), Some("\"b\""));
+        assert_eq!(object(text, 9, '"', false).as_deref(), Some("d"));
+        // between two strings is not inside one
+        assert_eq!(object(text, 6, '"', false), None);
+        assert_eq!(object("\"a\n\"", 1, '"', false), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+use crate::settings::{self, ColorSupport};
+
+const DEFAULT_THEME: &str = include_str!("../themes/default.toml");
+const MAX_INHERITS: usize = 8; // guards against themes inheriting from each other
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ThemeFile {
+    inherits: Option<String>,
+    palette: HashMap<String, String>, // color names usable in styles
+    styles: HashMap<String, StyleSpec>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+enum StyleSpec {
+    Fg(String),
+    Full {
+        fg: Option<String>,
+        bg: Option<String>,
+        underline: Option<String>,
+        #[serde(default)]
+        modifiers: Vec<String>,
+    },
+}
+
+/// Maps scopes like `keyword.function` or `ui.linenr` to styles. A scope that is
+/// not in the theme falls back to its parent (`keyword`), then to no style.
+/// Colors are reduced to what the terminal can show when the theme is loaded.
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<String, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let file = toml::from_str(DEFAULT_THEME).expect("built-in theme is valid");
+        Self::resolve("default", file, ColorSupport::TrueColor).expect("built-in theme is valid")
+    }
+}
+
+impl Theme {
+    pub fn get(&self, scope: &str) -> Style {
+        let mut scope = scope;
+        loop {
+            if let Some(style) = self.styles.get(scope) {
+                return *style;
+            }
+            match scope.rfind('.') {
+                Some(idx) => scope = &scope[..idx],
+                None => return Style::default(),
+            }
+        }
+    }
+
+    pub fn load(name: &str, colors: ColorSupport) -> io::Result<Self> {
+        let mut file = read_theme(name)?;
+        let mut depth = 0;
+        // parents first, the child overrides them scope by scope
+        while let Some(parent) = file.inherits.take() {
+            depth += 1;
+            if depth > MAX_INHERITS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("theme {}: too many inherited themes", name),
+                ));
+            }
+            let mut base = read_theme(&parent)?;
+            base.palette.extend(file.palette);
+            base.styles.extend(file.styles);
+            file = base;
+        }
+        Self::resolve(name, file, colors)
+    }
+
+    fn resolve(name: &str, file: ThemeFile, colors: ColorSupport) -> io::Result<Self> {
+        let colors = colors.detect();
+        let color = |text: &str| -> io::Result<Color> {
+            let text = file.palette.get(text).map(String::as_str).unwrap_or(text);
+            Color::from_str(text)
+                .map(|color| downgrade(color, colors))
+                .map_err(|_| invalid(name, format!("unknown color \"{}\"", text)))
+        };
+
+        let mut styles = HashMap::new();
+        for (scope, spec) in &file.styles {
+            let style = match spec {
+                StyleSpec::Fg(fg) => Style::default().fg(color(fg)?),
+                StyleSpec::Full {
+                    fg,
+                    bg,
+                    underline,
+                    modifiers,
+                } => {
+                    let mut style = Style::default();
+                    if let Some(fg) = fg {
+                        style = style.fg(color(fg)?);
+                    }
+                    if let Some(bg) = bg {
+                        style = style.bg(color(bg)?);
+                    }
+                    if let Some(underline) = underline {
+                        style = style.underline_color(color(underline)?);
+                    }
+                    for modifier in modifiers {
+                        style = style.add_modifier(parse_modifier(modifier).ok_or_else(|| {
+                            invalid(name, format!("unknown modifier \"{}\"", modifier))
+                        })?);
+                    }
+                    style
+                }
+            };
+            styles.insert(scope.clone(), style);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            styles,
+        })
+    }
+}
+
+fn invalid(name: &str, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("theme {}: {}", name, message),
+    )
+}
+
+// project themes shadow user themes, which shadow the built-in ones
+fn theme_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![settings::project_root().join(".elarge").join("themes")];
+    if let Some(config_dir) = settings::config_dir() {
+        dirs.push(config_dir.join("themes"));
+    }
+    dirs
+}
+
+// every theme `:theme` can load, for completion
+pub fn theme_names() -> Vec<String> {
+    let mut names = vec!["default".to_string()];
+    for dir in theme_dirs() {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+                && let Some(name) = path.file_stem()
+            {
+                names.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn read_theme(name: &str) -> io::Result<ThemeFile> {
+    let file_name = format!("{}.toml", name);
+    if Path::new(&file_name).components().count() != 1 {
+        return Err(invalid(name, "not a theme name".to_string()));
+    }
+    for dir in theme_dirs() {
+        let path = dir.join(&file_name);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        return toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e.message()),
+            )
+        });
+    }
+    match name {
+        "default" => Ok(toml::from_str(DEFAULT_THEME).expect("built-in theme is valid")),
+        _ => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("theme not found: {}", name),
+        )),
+    }
+}
+
+fn parse_modifier(text: &str) -> Option<Modifier> {
+    match text {
+        "bold" => Some(Modifier::BOLD),
+        "dim" => Some(Modifier::DIM),
+        "italic" => Some(Modifier::ITALIC),
+        "underlined" => Some(Modifier::UNDERLINED),
+        "slow_blink" => Some(Modifier::SLOW_BLINK),
+        "rapid_blink" => Some(Modifier::RAPID_BLINK),
+        "reversed" => Some(Modifier::REVERSED),
+        "hidden" => Some(Modifier::HIDDEN),
+        "crossed_out" => Some(Modifier::CROSSED_OUT),
+        _ => None,
+    }
+}
+
+impl ColorSupport {
+    // `auto` looks at the terminal, the usual variables are COLORTERM and TERM
+    pub fn detect(self) -> Self {
+        if self != ColorSupport::Auto {
+            return self;
+        }
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+}
+
+// xterm defaults for the 16 named colors, in index order
+const ANSI_16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn downgrade(color: Color, colors: ColorSupport) -> Color {
+    match (color, colors) {
+        (Color::Rgb(r, g, b), ColorSupport::Ansi256) => Color::Indexed(rgb_to_256((r, g, b))),
+        (Color::Rgb(r, g, b), ColorSupport::Ansi16) => nearest_16((r, g, b)),
+        (Color::Indexed(idx), ColorSupport::Ansi16) => nearest_16(indexed_to_rgb(idx)),
+        _ => color,
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> Color {
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+// closest entry of the 6x6x6 cube or the grayscale ramp
+fn rgb_to_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| match c {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (c - 35) / 40,
+    };
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = 16 + 36 * r + 6 * g + b;
+
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232 + ((average.saturating_sub(3) / 10).min(23)) as u8;
+
+    match distance(rgb, indexed_to_rgb(cube)) <= distance(rgb, indexed_to_rgb(gray)) {
+        true => cube,
+        false => gray,
+    }
+}
+
+fn indexed_to_rgb(idx: u8) -> (u8, u8, u8) {
+    match idx {
+        0..16 => ANSI_16[idx as usize].1,
+        16..232 => {
+            let idx = idx - 16;
+            (
+                CUBE_LEVELS[(idx / 36) as usize],
+                CUBE_LEVELS[(idx / 6 % 6) as usize],
+                CUBE_LEVELS[(idx % 6) as usize],
+            )
+        }
+        _ => {
+            let value = 8 + 10 * (idx - 232);
+            (value, value, value)
+        }
+    }
+}
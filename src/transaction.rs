@@ -1 +1,479 @@
-pub struct TranSaction {}
+use ropey::Rope;
+use std::ops::Range;
+
+// single step of a change set, lengths are counted in chars
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Retain(usize),
+    Delete(usize),
+    Insert(String),
+}
+
+// replace `from..to` with the given text (None only deletes)
+pub type Change = (usize, usize, Option<String>);
+
+// which side a position sticks to when text is inserted right at it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Before,
+    After,
+}
+
+/// Change set over char offsets that walks the whole document from start to
+/// end. Every edit of the text goes through one of these, so it can be
+/// applied, inverted (for undo) and composed (for grouping edits).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranSaction {
+    operations: Vec<Operation>,
+    len: usize,       // length of the document the transaction applies to
+    len_after: usize, // length of the document after applying
+}
+
+impl TranSaction {
+    // changes must be sorted and must not overlap
+    pub fn change<I>(doc: &Rope, changes: I) -> Self
+    where
+        I: IntoIterator<Item = Change>,
+    {
+        let len = doc.len_chars();
+        let mut transaction = Self {
+            operations: Vec::new(),
+            len,
+            len_after: len,
+        };
+        let mut last = 0;
+        for (from, to, text) in changes {
+            debug_assert!(last <= from && from <= to && to <= len);
+            transaction.push_retain(from - last);
+            transaction.push_delete(to - from);
+            if let Some(text) = text {
+                transaction.push_insert(text);
+            }
+            last = to;
+        }
+        transaction.push_retain(len - last);
+        transaction
+    }
+
+    pub fn insert(doc: &Rope, idx: usize, text: &str) -> Self {
+        Self::change(doc, [(idx, idx, Some(text.to_string()))])
+    }
+
+    pub fn delete(doc: &Rope, range: Range<usize>) -> Self {
+        Self::change(doc, [(range.start, range.end, None)])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations
+            .iter()
+            .all(|operation| matches!(operation, Operation::Retain(_)))
+    }
+
+    // returns false (leaving the rope untouched) when the rope does not match
+    pub fn apply(&self, doc: &mut Rope) -> bool {
+        if doc.len_chars() != self.len {
+            return false;
+        }
+        let mut pos = 0;
+        for operation in &self.operations {
+            match operation {
+                Operation::Retain(n) => pos += n,
+                Operation::Delete(n) => doc.remove(pos..pos + n),
+                Operation::Insert(text) => {
+                    doc.insert(pos, text);
+                    pos += text.chars().count();
+                }
+            }
+        }
+        true
+    }
+
+    // transaction that reverts self, `original` is the text before self was applied
+    pub fn invert(&self, original: &Rope) -> Self {
+        let mut inverted = Self {
+            operations: Vec::with_capacity(self.operations.len()),
+            len: self.len_after,
+            len_after: self.len_after,
+        };
+        let mut pos = 0;
+        for operation in &self.operations {
+            match operation {
+                Operation::Retain(n) => {
+                    inverted.push_retain(*n);
+                    pos += n;
+                }
+                Operation::Delete(n) => {
+                    inverted.push_insert(original.slice(pos..pos + n).to_string());
+                    pos += n;
+                }
+                Operation::Insert(text) => inverted.push_delete(text.chars().count()),
+            }
+        }
+        inverted
+    }
+
+    // single transaction with the same effect as applying self and then other
+    pub fn compose(self, other: Self) -> Self {
+        debug_assert_eq!(self.len_after, other.len);
+        let mut composed = Self {
+            operations: Vec::with_capacity(self.operations.len()),
+            len: self.len,
+            len_after: self.len,
+        };
+        let mut ops_a = self.operations.into_iter();
+        let mut ops_b = other.operations.into_iter();
+        let mut head_a = ops_a.next();
+        let mut head_b = ops_b.next();
+
+        loop {
+            use Operation::*;
+            use std::cmp::Ordering;
+            match (head_a, head_b) {
+                (None, None) => break,
+                // deletes of the first and inserts of the second pass through untouched
+                (Some(Delete(n)), b) => {
+                    composed.push_delete(n);
+                    head_a = ops_a.next();
+                    head_b = b;
+                }
+                (a, Some(Insert(text))) => {
+                    composed.push_insert(text);
+                    head_a = a;
+                    head_b = ops_b.next();
+                }
+                (None, _) | (_, None) => unreachable!("transactions do not cover the same text"),
+                (Some(Retain(i)), Some(Retain(j))) => {
+                    composed.push_retain(i.min(j));
+                    match i.cmp(&j) {
+                        Ordering::Less => {
+                            head_a = ops_a.next();
+                            head_b = Some(Retain(j - i));
+                        }
+                        Ordering::Equal => {
+                            head_a = ops_a.next();
+                            head_b = ops_b.next();
+                        }
+                        Ordering::Greater => {
+                            head_a = Some(Retain(i - j));
+                            head_b = ops_b.next();
+                        }
+                    }
+                }
+                (Some(Retain(i)), Some(Delete(j))) => {
+                    composed.push_delete(i.min(j));
+                    match i.cmp(&j) {
+                        Ordering::Less => {
+                            head_a = ops_a.next();
+                            head_b = Some(Delete(j - i));
+                        }
+                        Ordering::Equal => {
+                            head_a = ops_a.next();
+                            head_b = ops_b.next();
+                        }
+                        Ordering::Greater => {
+                            head_a = Some(Retain(i - j));
+                            head_b = ops_b.next();
+                        }
+                    }
+                }
+                (Some(Insert(text)), Some(Retain(j))) => {
+                    let len = text.chars().count();
+                    match len.cmp(&j) {
+                        Ordering::Less => {
+                            composed.push_insert(text);
+                            head_a = ops_a.next();
+                            head_b = Some(Retain(j - len));
+                        }
+                        Ordering::Equal => {
+                            composed.push_insert(text);
+                            head_a = ops_a.next();
+                            head_b = ops_b.next();
+                        }
+                        Ordering::Greater => {
+                            let (kept, rest) = split_at_char(&text, j);
+                            composed.push_insert(kept.to_string());
+                            head_a = Some(Insert(rest.to_string()));
+                            head_b = ops_b.next();
+                        }
+                    }
+                }
+                // text inserted by the first and deleted by the second cancels out
+                (Some(Insert(text)), Some(Delete(j))) => {
+                    let len = text.chars().count();
+                    match len.cmp(&j) {
+                        Ordering::Less => {
+                            head_a = ops_a.next();
+                            head_b = Some(Delete(j - len));
+                        }
+                        Ordering::Equal => {
+                            head_a = ops_a.next();
+                            head_b = ops_b.next();
+                        }
+                        Ordering::Greater => {
+                            let (_, rest) = split_at_char(&text, j);
+                            head_a = Some(Insert(rest.to_string()));
+                            head_b = ops_b.next();
+                        }
+                    }
+                }
+            }
+        }
+        composed
+    }
+}
+
+impl TranSaction {
+    // span covering every change: (from, end in the old text, end in the new text)
+    pub fn changed_range(&self) -> Option<(usize, usize, usize)> {
+        let mut old_pos = 0;
+        let mut new_pos = 0;
+        let mut changed: Option<(usize, usize, usize)> = None;
+        for operation in &self.operations {
+            let from = old_pos;
+            match operation {
+                Operation::Retain(n) => {
+                    old_pos += n;
+                    new_pos += n;
+                    continue;
+                }
+                Operation::Delete(n) => old_pos += n,
+                Operation::Insert(text) => new_pos += text.chars().count(),
+            }
+            let start = changed.map_or(from, |(start, _, _)| start);
+            changed = Some((start, old_pos, new_pos));
+        }
+        changed
+    }
+
+    // where a char offset of the old text ends up in the new text
+    pub fn map_pos(&self, pos: usize, assoc: Assoc) -> usize {
+        let mut old_pos = 0;
+        let mut new_pos = 0;
+        let mut operations = self.operations.iter().peekable();
+
+        while let Some(operation) = operations.next() {
+            match operation {
+                Operation::Retain(n) => {
+                    if old_pos + n > pos {
+                        return new_pos + (pos - old_pos);
+                    }
+                    old_pos += n;
+                    new_pos += n;
+                }
+                Operation::Delete(n) => {
+                    // positions inside deleted text collapse to where it was
+                    if old_pos + n > pos {
+                        return new_pos;
+                    }
+                    old_pos += n;
+                }
+                Operation::Insert(text) => {
+                    let len = text.chars().count();
+                    // insert followed by delete replaces text
+                    if let Some(Operation::Delete(n)) = operations.peek() {
+                        let n = *n;
+                        operations.next();
+                        if old_pos + n > pos {
+                            return match pos == old_pos || assoc == Assoc::Before {
+                                true => new_pos,
+                                false => new_pos + len,
+                            };
+                        }
+                        old_pos += n;
+                    } else if old_pos == pos {
+                        return match assoc {
+                            Assoc::Before => new_pos,
+                            Assoc::After => new_pos + len,
+                        };
+                    }
+                    new_pos += len;
+                }
+            }
+        }
+        // past the end of the text
+        new_pos + pos.saturating_sub(old_pos)
+    }
+
+    // (Before, After) grows when text is inserted at the edges, (After, Before) does not
+    pub fn map_range(&self, range: &Range<usize>, start: Assoc, end: Assoc) -> Range<usize> {
+        let start = self.map_pos(range.start, start);
+        let end = self.map_pos(range.end, end).max(start);
+        start..end
+    }
+}
+
+impl TranSaction {
+    // builder helpers, merge with the previous operation when possible
+    fn push_retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.operations.last_mut() {
+            Some(Operation::Retain(count)) => *count += n,
+            _ => self.operations.push(Operation::Retain(n)),
+        }
+    }
+
+    fn push_delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        self.len_after -= n;
+        match self.operations.last_mut() {
+            Some(Operation::Delete(count)) => *count += n,
+            _ => self.operations.push(Operation::Delete(n)),
+        }
+    }
+
+    fn push_insert(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        self.len_after += text.chars().count();
+        // keep inserts before deletes so equal transactions look the same
+        match self.operations.as_mut_slice() {
+            [.., Operation::Insert(prev)] | [.., Operation::Insert(prev), Operation::Delete(_)] => {
+                prev.push_str(&text)
+            }
+            [.., last @ Operation::Delete(_)] => {
+                let delete = std::mem::replace(last, Operation::Insert(text));
+                self.operations.push(delete);
+            }
+            _ => self.operations.push(Operation::Insert(text)),
+        }
+    }
+}
+
+fn split_at_char(text: &str, char_idx: usize) -> (&str, &str) {
+    let byte_idx = text
+        .char_indices()
+        .nth(char_idx)
+        .map_or(text.len(), |(idx, _)| idx);
+    text.split_at(byte_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(transaction: &TranSaction, text: &Rope) -> Rope {
+        let mut text = text.clone();
+        assert!(transaction.apply(&mut text));
+        text
+    }
+
+    #[test]
+    fn apply_changes() {
+        let text = Rope::from("hello world");
+        let transaction = TranSaction::change(
+            &text,
+            [
+                (0, 5, Some("bye".to_string())),
+                (6, 6, Some("big ".to_string())),
+            ],
+        );
+        assert_eq!(applied(&transaction, &text), "bye big world");
+    }
+
+    #[test]
+    fn apply_refuses_other_lengths() {
+        let transaction = TranSaction::insert(&Rope::from("abc"), 1, "x");
+        let mut text = Rope::from("abcd");
+        assert!(!transaction.apply(&mut text));
+        assert_eq!(text, "abcd");
+    }
+
+    #[test]
+    fn invert_restores_the_text() {
+        let text = Rope::from("fn main() {}");
+        let transaction = TranSaction::change(
+            &text,
+            [
+                (0, 2, Some("pub fn".to_string())),
+                (3, 7, None),
+                (11, 11, Some("ü".to_string())),
+            ],
+        );
+        let changed = applied(&transaction, &text);
+        assert_eq!(changed, "pub fn () {ü}");
+        assert_eq!(applied(&transaction.invert(&text), &changed), text);
+    }
+
+    #[test]
+    fn compose_matches_applying_in_turn() {
+        let text = Rope::from("one two three");
+        let first = TranSaction::change(&text, [(4, 7, Some("2".to_string()))]);
+        let middle = applied(&first, &text);
+        // deletes part of the inserted text and inserts around it
+        let second = TranSaction::change(
+            &middle,
+            [
+                (0, 0, Some(">".to_string())),
+                (4, 5, Some("zwei".to_string())),
+                (6, 11, None),
+            ],
+        );
+        let end = applied(&second, &middle);
+        let composed = first.clone().compose(second.clone());
+        assert_eq!(applied(&composed, &text), end);
+        // undoing the composition undoes both
+        assert_eq!(applied(&composed.invert(&text), &end), text);
+        let undo = second.invert(&middle).compose(first.invert(&text));
+        assert_eq!(applied(&undo, &end), text);
+    }
+
+    #[test]
+    fn compose_cancels_insert_then_delete() {
+        let text = Rope::from("abc");
+        let insert = TranSaction::insert(&text, 1, "xyz");
+        let delete = TranSaction::delete(&applied(&insert, &text), 1..4);
+        assert!(insert.compose(delete).is_empty());
+    }
+
+    #[test]
+    fn map_pos_at_insert_edges() {
+        let text = Rope::from("abcd");
+        let transaction = TranSaction::insert(&text, 2, "xy");
+        assert_eq!(transaction.map_pos(1, Assoc::After), 1);
+        assert_eq!(transaction.map_pos(2, Assoc::Before), 2);
+        assert_eq!(transaction.map_pos(2, Assoc::After), 4);
+        assert_eq!(transaction.map_pos(3, Assoc::Before), 5);
+        assert_eq!(transaction.map_pos(4, Assoc::Before), 6);
+    }
+
+    #[test]
+    fn map_pos_around_deletes_and_replacements() {
+        let text = Rope::from("abcdef");
+        let delete = TranSaction::delete(&text, 1..3);
+        assert_eq!(delete.map_pos(1, Assoc::After), 1);
+        assert_eq!(delete.map_pos(2, Assoc::After), 1);
+        assert_eq!(delete.map_pos(3, Assoc::Before), 1);
+        assert_eq!(delete.map_pos(5, Assoc::Before), 3);
+
+        let replace = TranSaction::change(&text, [(1, 3, Some("xyz".to_string()))]);
+        assert_eq!(replace.map_pos(1, Assoc::After), 1);
+        assert_eq!(replace.map_pos(2, Assoc::Before), 1);
+        assert_eq!(replace.map_pos(2, Assoc::After), 4);
+        assert_eq!(replace.map_pos(3, Assoc::Before), 4);
+    }
+
+    #[test]
+    fn map_range_grows_or_stays() {
+        let text = Rope::from("abcdef");
+        let transaction = TranSaction::change(
+            &text,
+            [(2, 2, Some("<".to_string())), (4, 4, Some(">".to_string()))],
+        );
+        assert_eq!(
+            transaction.map_range(&(2..4), Assoc::Before, Assoc::After),
+            2..6
+        );
+        assert_eq!(
+            transaction.map_range(&(2..4), Assoc::After, Assoc::Before),
+            3..5
+        );
+        // a range whose text is deleted collapses
+        let delete = TranSaction::delete(&text, 1..5);
+        assert_eq!(delete.map_range(&(2..4), Assoc::Before, Assoc::After), 1..1);
+    }
+}